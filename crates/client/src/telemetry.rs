@@ -1,5 +1,6 @@
 use crate::http::HttpClient;
 use db::Db;
+use futures::future::join_all;
 use gpui::{
     executor::Background,
     serde_json::{self, value::Map, Value},
@@ -8,14 +9,14 @@ use gpui::{
 use isahc::Request;
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
     io::Write,
     mem,
     path::PathBuf,
     sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tempfile::NamedTempFile;
 use util::{post_inc, ResultExt, TryFutureExt};
@@ -24,20 +25,125 @@ use uuid::Uuid;
 pub struct Telemetry {
     http_client: Arc<dyn HttpClient>,
     executor: Arc<Background>,
+    sinks: Vec<Arc<dyn TelemetrySink>>,
     state: Mutex<TelemetryState>,
 }
 
 #[derive(Default)]
 struct TelemetryState {
+    db: Option<Db>,
     metrics_id: Option<Arc<str>>,
     device_id: Option<Arc<str>>,
     app_version: Option<Arc<str>>,
     os_version: Option<Arc<str>>,
     os_name: &'static str,
-    queue: Vec<MixpanelEvent>,
+    queue: Vec<Event>,
     next_event_id: usize,
     flush_task: Option<Task<()>>,
+    retry_backoff: Duration,
     log_file: Option<NamedTempFile>,
+    settings: TelemetrySettings,
+    scrubbed_property_keys: Arc<Vec<String>>,
+    /// `server_time - local_time`, in milliseconds, as of the last time we computed it from a
+    /// response's `Date` header. `None` until the first successful flush, in which case events
+    /// are reported with their raw local time.
+    clock_offset_millis: Option<i64>,
+    clock_offset_checked_at: Option<Instant>,
+}
+
+/// Consent controls for telemetry, kept in `TelemetryState` and updated via
+/// `Telemetry::set_telemetry_settings` whenever the user's settings change. Lets users and
+/// enterprise deployers control exactly what, if anything, leaves the machine.
+#[derive(Clone, Copy)]
+pub struct TelemetrySettings {
+    /// Whether to report events at all. When `false`, `report_event` drops events without
+    /// touching the queue, `log_file`, or the network, and `flush` is a no-op.
+    pub telemetry: bool,
+    /// When `true` (and `telemetry` is `true`), events are still collected and written to
+    /// `log_file` for local inspection, but never sent to a `TelemetrySink`.
+    pub diagnostics_only: bool,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            telemetry: true,
+            // Debug builds default to local-only logging, so day-to-day development doesn't send
+            // events to the production analytics destinations. Packaged and `--release` builds
+            // default to full reporting.
+            diagnostics_only: cfg!(debug_assertions),
+        }
+    }
+}
+
+/// Redacts the value of any `properties` entry whose key appears in `scrubbed_keys` (e.g. file
+/// paths or names), so the raw value is never serialized into an outgoing request body or the
+/// local `log_file`.
+fn redact_properties(
+    mut properties: Option<Map<String, Value>>,
+    scrubbed_keys: &[String],
+) -> Option<Map<String, Value>> {
+    if let Some(properties) = &mut properties {
+        for key in scrubbed_keys {
+            if let Some(value) = properties.get_mut(key) {
+                *value = Value::String("<redacted>".to_string());
+            }
+        }
+    }
+    properties
+}
+
+impl TelemetryState {
+    /// Persists the current queue to disk so it survives a crash or quit before it's flushed.
+    /// Called after every mutation to `queue` so nothing reported is ever only held in memory.
+    fn persist_queue(&self) {
+        let Some(db) = &self.db else {
+            return;
+        };
+        if let Some(json) = serde_json::to_string(&self.queue).log_err() {
+            db.write_kvp(QUEUE_KVP_KEY, &json).log_err();
+        }
+    }
+}
+
+/// A single reported event, independent of which `TelemetrySink`(s) it ends up posted to. Kept
+/// generic (rather than the Mixpanel wire format directly) so the same queue can be fanned out to
+/// every active sink without coupling the event model to one destination's schema.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Event {
+    kind: String,
+    time: u128,
+    device_id: Option<Arc<str>>,
+    insert_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none", flatten)]
+    properties: Option<Map<String, Value>>,
+    os_name: &'static str,
+    os_version: Option<Arc<str>>,
+    app_version: Option<Arc<str>>,
+    signed_in: bool,
+}
+
+/// A destination `Telemetry` can report events to. Implementations own their own wire format and
+/// credentials; `Telemetry` just collects generic `Event`s and asks every active sink to serialize
+/// and ship its own batch, so adding a new analytics provider or running two side by side during a
+/// migration doesn't touch `report_event`.
+trait TelemetrySink: Send + Sync + 'static {
+    /// Where to POST the bytes produced by `serialize_batch`.
+    fn events_url(&self) -> &'static str;
+
+    /// Serializes a batch of events into this sink's wire format, applying `clock_offset_millis`
+    /// (see `Telemetry::flush`) to each event's reported time.
+    fn serialize_batch(&self, events: &[Event], clock_offset_millis: i64) -> anyhow::Result<Vec<u8>>;
+
+    /// Builds a request (url, body) that associates `device_id` with the given identity traits,
+    /// for sinks that support an out-of-band identify call. Sinks that fold identity into every
+    /// event instead return `None`.
+    fn identify(
+        &self,
+        device_id: &Arc<str>,
+        metrics_id: Option<Arc<str>>,
+        is_staff: bool,
+    ) -> Option<(&'static str, Vec<u8>)>;
 }
 
 const MIXPANEL_EVENTS_URL: &'static str = "https://api.mixpanel.com/track";
@@ -61,6 +167,9 @@ struct MixpanelEventProperties {
     #[serde(skip_serializing_if = "str::is_empty")]
     token: &'static str,
     time: u128,
+    /// The event's unadjusted local timestamp, kept alongside the clock-offset-corrected `time`
+    /// purely for debugging a machine's clock skew.
+    local_time: u128,
     distinct_id: Option<Arc<str>>,
     #[serde(rename = "$insert_id")]
     insert_id: usize,
@@ -84,6 +193,132 @@ struct MixpanelEngageRequest {
     set: Value,
 }
 
+struct MixpanelSink {
+    token: &'static str,
+}
+
+impl TelemetrySink for MixpanelSink {
+    fn events_url(&self) -> &'static str {
+        MIXPANEL_EVENTS_URL
+    }
+
+    fn serialize_batch(&self, events: &[Event], clock_offset_millis: i64) -> anyhow::Result<Vec<u8>> {
+        let events = events
+            .iter()
+            .map(|event| MixpanelEvent {
+                event: event.kind.clone(),
+                properties: MixpanelEventProperties {
+                    token: self.token,
+                    time: adjusted_time(event.time, clock_offset_millis),
+                    local_time: event.time,
+                    distinct_id: event.device_id.clone(),
+                    insert_id: event.insert_id,
+                    event_properties: event.properties.clone(),
+                    os_name: event.os_name,
+                    os_version: event.os_version.clone(),
+                    app_version: event.app_version.clone(),
+                    signed_in: event.signed_in,
+                    platform: "Zed",
+                },
+            })
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_vec(&events)?)
+    }
+
+    fn identify(
+        &self,
+        device_id: &Arc<str>,
+        metrics_id: Option<Arc<str>>,
+        is_staff: bool,
+    ) -> Option<(&'static str, Vec<u8>)> {
+        let json_bytes = serde_json::to_vec(&[MixpanelEngageRequest {
+            token: self.token,
+            distinct_id: device_id.clone(),
+            set: json!({ "staff": is_staff, "id": metrics_id }),
+        }])
+        .log_err()?;
+        Some((MIXPANEL_ENGAGE_URL, json_bytes))
+    }
+}
+
+const AMPLITUDE_EVENTS_URL: &'static str = "https://api2.amplitude.com/2/httpapi";
+
+lazy_static! {
+    static ref AMPLITUDE_API_KEY: Option<String> = std::env::var("ZED_AMPLITUDE_API_KEY")
+        .ok()
+        .or_else(|| option_env!("ZED_AMPLITUDE_API_KEY").map(|key| key.to_string()));
+}
+
+#[derive(Serialize)]
+struct AmplitudeEventBatch {
+    api_key: &'static str,
+    events: Vec<AmplitudeEvent>,
+}
+
+#[derive(Serialize)]
+struct AmplitudeEvent {
+    event_type: String,
+    device_id: Option<Arc<str>>,
+    time: u128,
+    /// The event's unadjusted local timestamp, kept alongside the clock-offset-corrected `time`
+    /// purely for debugging a machine's clock skew.
+    local_time: u128,
+    insert_id: String,
+    platform: &'static str,
+    user_properties: Map<String, Value>,
+}
+
+struct AmplitudeSink {
+    api_key: &'static str,
+}
+
+impl TelemetrySink for AmplitudeSink {
+    fn events_url(&self) -> &'static str {
+        AMPLITUDE_EVENTS_URL
+    }
+
+    fn serialize_batch(&self, events: &[Event], clock_offset_millis: i64) -> anyhow::Result<Vec<u8>> {
+        let events = events
+            .iter()
+            .map(|event| {
+                // Amplitude has no dedicated os_version/app_version columns in this integration,
+                // so fold them into user_properties alongside the event's own custom properties.
+                let mut user_properties = event.properties.clone().unwrap_or_default();
+                if let Some(os_version) = &event.os_version {
+                    user_properties.insert("os_version".into(), json!(os_version.to_string()));
+                }
+                if let Some(app_version) = &event.app_version {
+                    user_properties.insert("app_version".into(), json!(app_version.to_string()));
+                }
+                AmplitudeEvent {
+                    event_type: event.kind.clone(),
+                    device_id: event.device_id.clone(),
+                    time: adjusted_time(event.time, clock_offset_millis),
+                    local_time: event.time,
+                    insert_id: event.insert_id.to_string(),
+                    platform: "Zed",
+                    user_properties,
+                }
+            })
+            .collect();
+        Ok(serde_json::to_vec(&AmplitudeEventBatch {
+            api_key: self.api_key,
+            events,
+        })?)
+    }
+
+    fn identify(
+        &self,
+        _device_id: &Arc<str>,
+        _metrics_id: Option<Arc<str>>,
+        _is_staff: bool,
+    ) -> Option<(&'static str, Vec<u8>)> {
+        // No out-of-band identify call is made, and `serialize_batch` doesn't add the metrics id
+        // or staff flag to events either, so Amplitude never receives these identity traits.
+        None
+    }
+}
+
 #[cfg(debug_assertions)]
 const MAX_QUEUE_LEN: usize = 1;
 
@@ -96,13 +331,71 @@ const DEBOUNCE_INTERVAL: Duration = Duration::from_secs(1);
 #[cfg(not(debug_assertions))]
 const DEBOUNCE_INTERVAL: Duration = Duration::from_secs(30);
 
+/// The on-disk key events are persisted under between being reported and successfully flushed, so
+/// they survive a crash or quit and get retried on the next launch.
+const QUEUE_KVP_KEY: &str = "telemetry-queue";
+
+/// Caps how many un-sent events we'll keep on disk. Once a retry batch fails enough times (or
+/// enough events pile up while offline) to exceed this, we drop the oldest ones rather than
+/// growing the persisted queue without bound.
+const MAX_PERSISTED_EVENTS: usize = 500;
+
+const RETRY_BACKOFF_INITIAL: Duration = Duration::from_secs(30);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+
+/// How often to recompute the server/local clock offset from a response's `Date` header. More
+/// frequent than this just adds header-parsing overhead for no practical benefit -- a system
+/// clock doesn't drift meaningfully within an hour.
+const CLOCK_OFFSET_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Reads the `Date` header off an HTTP response and returns `server_time - local_time` in
+/// milliseconds: positive when the local clock is behind the server, negative when it's ahead.
+fn clock_offset_millis_from_response<T>(response: &isahc::http::Response<T>) -> Option<i64> {
+    let date = response.headers().get(isahc::http::header::DATE)?.to_str().ok()?;
+    let server_time = httpdate::parse_http_date(date).ok()?;
+    Some(match server_time.duration_since(SystemTime::now()) {
+        Ok(ahead) => ahead.as_millis() as i64,
+        Err(behind) => -(behind.duration().as_millis() as i64),
+    })
+}
+
+/// Applies the current server/local clock offset to a locally-recorded timestamp, so events keep
+/// trustworthy ordering and absolute times across machines with a skewed system clock. Clamped to
+/// zero rather than allowed to go negative.
+fn adjusted_time(local_time: u128, clock_offset_millis: i64) -> u128 {
+    (local_time as i64 + clock_offset_millis).max(0) as u128
+}
+
+/// Adds up to 20% random jitter to a backoff duration, so that clients whose flushes failed at
+/// around the same time (e.g. because a sink's endpoint was briefly down) don't all retry in
+/// lockstep.
+fn jittered(duration: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    let jitter = (nanos % 1000) as f64 / 1000.0 * 0.2;
+    duration.mul_f64(1.0 + jitter)
+}
+
 impl Telemetry {
     pub fn new(client: Arc<dyn HttpClient>, cx: &AppContext) -> Arc<Self> {
         let platform = cx.platform();
+
+        let mut sinks: Vec<Arc<dyn TelemetrySink>> = Vec::new();
+        if let Some(token) = MIXPANEL_TOKEN.as_ref() {
+            sinks.push(Arc::new(MixpanelSink { token }));
+        }
+        if let Some(api_key) = AMPLITUDE_API_KEY.as_ref() {
+            sinks.push(Arc::new(AmplitudeSink { api_key }));
+        }
+
         let this = Arc::new(Self {
             http_client: client,
             executor: cx.background().clone(),
+            sinks,
             state: Mutex::new(TelemetryState {
+                db: None,
                 os_version: platform
                     .os_version()
                     .log_err()
@@ -116,12 +409,17 @@ impl Telemetry {
                 metrics_id: None,
                 queue: Default::default(),
                 flush_task: Default::default(),
+                retry_backoff: RETRY_BACKOFF_INITIAL,
                 next_event_id: 0,
                 log_file: None,
+                settings: TelemetrySettings::default(),
+                scrubbed_property_keys: Default::default(),
+                clock_offset_millis: None,
+                clock_offset_checked_at: None,
             }),
         });
 
-        if MIXPANEL_TOKEN.is_some() {
+        if !this.sinks.is_empty() {
             this.executor
                 .spawn({
                     let this = this.clone();
@@ -141,6 +439,28 @@ impl Telemetry {
         Some(self.state.lock().log_file.as_ref()?.path().to_path_buf())
     }
 
+    /// Updates the consent flags consulted by `report_event`, `set_authenticated_user_info`, and
+    /// `flush`. Call this whenever the user's settings change.
+    pub fn set_telemetry_settings(self: &Arc<Self>, settings: TelemetrySettings) {
+        self.state.lock().settings = settings;
+    }
+
+    /// Registers the `event_properties` keys (e.g. `"path"`, `"file_name"`) whose values should be
+    /// redacted before an event is serialized, for either the outgoing request body or the local
+    /// `log_file`.
+    pub fn set_scrubbed_property_keys(self: &Arc<Self>, keys: Vec<String>) {
+        self.state.lock().scrubbed_property_keys = Arc::new(keys);
+    }
+
+    /// Whether it's been long enough since the clock offset was last computed (or it's never been
+    /// computed at all) that we should spend a response's `Date` header recomputing it again.
+    fn should_recompute_clock_offset(&self) -> bool {
+        match self.state.lock().clock_offset_checked_at {
+            Some(checked_at) => checked_at.elapsed() >= CLOCK_OFFSET_REFRESH_INTERVAL,
+            None => true,
+        }
+    }
+
     pub fn start(self: &Arc<Self>, db: Db) {
         let this = self.clone();
         self.executor
@@ -154,14 +474,26 @@ impl Telemetry {
                         device_id
                     };
 
+                    // Re-queue whatever didn't make it out before the last time we quit (or
+                    // crashed), ahead of anything reported so far this session.
+                    let persisted_events: Vec<Event> = db
+                        .read_kvp(QUEUE_KVP_KEY)
+                        .log_err()
+                        .flatten()
+                        .and_then(|json| serde_json::from_str(&json).log_err())
+                        .unwrap_or_default();
+
                     let device_id: Arc<str> = device_id.into();
                     let mut state = this.state.lock();
+                    state.db = Some(db);
                     state.device_id = Some(device_id.clone());
+                    if !persisted_events.is_empty() {
+                        let mut queue = persisted_events;
+                        queue.append(&mut state.queue);
+                        state.queue = queue;
+                    }
                     for event in &mut state.queue {
-                        event
-                            .properties
-                            .distinct_id
-                            .get_or_insert_with(|| device_id.clone());
+                        event.device_id.get_or_insert_with(|| device_id.clone());
                     }
                     if !state.queue.is_empty() {
                         drop(state);
@@ -182,23 +514,29 @@ impl Telemetry {
     ) {
         let this = self.clone();
         let mut state = self.state.lock();
+        let telemetry_enabled = state.settings.telemetry;
         let device_id = state.device_id.clone();
         let metrics_id: Option<Arc<str>> = metrics_id.map(|id| id.into());
         state.metrics_id = metrics_id.clone();
         drop(state);
 
-        if let Some((token, device_id)) = MIXPANEL_TOKEN.as_ref().zip(device_id) {
+        if !telemetry_enabled {
+            return;
+        }
+        let Some(device_id) = device_id else {
+            return;
+        };
+        for sink in self.sinks.iter().cloned() {
+            let Some((url, body)) = sink.identify(&device_id, metrics_id.clone(), is_staff) else {
+                continue;
+            };
+            let this = this.clone();
             self.executor
                 .spawn(
                     async move {
-                        let json_bytes = serde_json::to_vec(&[MixpanelEngageRequest {
-                            token,
-                            distinct_id: device_id,
-                            set: json!({ "staff": is_staff, "id": metrics_id }),
-                        }])?;
-                        let request = Request::post(MIXPANEL_ENGAGE_URL)
+                        let request = Request::post(url)
                             .header("Content-Type", "application/json")
-                            .body(json_bytes.into())?;
+                            .body(body.into())?;
                         this.http_client.send(request).await?;
                         Ok(())
                     }
@@ -210,29 +548,35 @@ impl Telemetry {
 
     pub fn report_event(self: &Arc<Self>, kind: &str, properties: Value) {
         let mut state = self.state.lock();
-        let event = MixpanelEvent {
-            event: kind.to_string(),
-            properties: MixpanelEventProperties {
-                token: "",
-                time: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-                distinct_id: state.device_id.clone(),
-                insert_id: post_inc(&mut state.next_event_id),
-                event_properties: if let Value::Object(properties) = properties {
-                    Some(properties)
-                } else {
-                    None
-                },
-                os_name: state.os_name,
-                os_version: state.os_version.clone(),
-                app_version: state.app_version.clone(),
-                signed_in: state.metrics_id.is_some(),
-                platform: "Zed",
-            },
+        if !state.settings.telemetry {
+            return;
+        }
+        let properties = if let Value::Object(properties) = properties {
+            Some(properties)
+        } else {
+            None
+        };
+        let properties = redact_properties(properties, &state.scrubbed_property_keys);
+        let event = Event {
+            kind: kind.to_string(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            device_id: state.device_id.clone(),
+            insert_id: post_inc(&mut state.next_event_id),
+            properties,
+            os_name: state.os_name,
+            os_version: state.os_version.clone(),
+            app_version: state.app_version.clone(),
+            signed_in: state.metrics_id.is_some(),
         };
         state.queue.push(event);
+        if state.queue.len() > MAX_PERSISTED_EVENTS {
+            let overflow = state.queue.len() - MAX_PERSISTED_EVENTS;
+            state.queue.drain(..overflow);
+        }
+        state.persist_queue();
         if state.device_id.is_some() {
             if state.queue.len() >= MAX_QUEUE_LEN {
                 drop(state);
@@ -250,40 +594,131 @@ impl Telemetry {
 
     fn flush(self: &Arc<Self>) {
         let mut state = self.state.lock();
-        let mut events = mem::take(&mut state.queue);
+        if !state.settings.telemetry {
+            // Leave the queue and `log_file` untouched -- if the user re-enables telemetry later,
+            // whatever was queued while it was off is still there to send.
+            state.flush_task.take();
+            return;
+        }
+        let diagnostics_only = state.settings.diagnostics_only;
+        let clock_offset_millis = state.clock_offset_millis.unwrap_or(0);
+        let events = mem::take(&mut state.queue);
         state.flush_task.take();
         drop(state);
 
-        if let Some(token) = MIXPANEL_TOKEN.as_ref() {
+        if events.is_empty() {
+            return;
+        }
+        let events = Arc::new(events);
+
+        {
             let this = self.clone();
+            let events = events.clone();
             self.executor
                 .spawn(
                     async move {
-                        let mut json_bytes = Vec::new();
-
                         if let Some(file) = &mut this.state.lock().log_file {
                             let file = file.as_file_mut();
-                            for event in &mut events {
+                            let mut json_bytes = Vec::new();
+                            for event in events.iter() {
                                 json_bytes.clear();
                                 serde_json::to_writer(&mut json_bytes, event)?;
                                 file.write_all(&json_bytes)?;
                                 file.write(b"\n")?;
-
-                                event.properties.token = token;
                             }
                         }
-
-                        json_bytes.clear();
-                        serde_json::to_writer(&mut json_bytes, &events)?;
-                        let request = Request::post(MIXPANEL_EVENTS_URL)
-                            .header("Content-Type", "application/json")
-                            .body(json_bytes.into())?;
-                        this.http_client.send(request).await?;
-                        Ok(())
+                        anyhow::Ok(())
                     }
                     .log_err(),
                 )
                 .detach();
         }
+
+        if diagnostics_only || self.sinks.is_empty() {
+            // Nothing left to retry -- these events were only ever destined for `log_file`.
+            self.retry_succeeded();
+            return;
+        }
+
+        let this = self.clone();
+        self.executor
+            .spawn(async move {
+                let sends = this.sinks.iter().cloned().map(|sink| {
+                    let this = this.clone();
+                    let events = events.clone();
+                    async move {
+                        let json_bytes = sink.serialize_batch(&events, clock_offset_millis)?;
+                        let request = Request::post(sink.events_url())
+                            .header("Content-Type", "application/json")
+                            .body(json_bytes.into())?;
+                        let response = this.http_client.send(request).await?;
+                        let new_clock_offset_millis = this
+                            .should_recompute_clock_offset()
+                            .then(|| clock_offset_millis_from_response(&response))
+                            .flatten();
+                        anyhow::Ok(new_clock_offset_millis)
+                    }
+                });
+                // Collect eagerly (rather than short-circuiting on `all`) so a failing sink
+                // doesn't suppress the error logging of the ones after it.
+                let results: Vec<Option<Option<i64>>> = join_all(sends)
+                    .await
+                    .into_iter()
+                    .map(|result| result.log_err())
+                    .collect();
+
+                if results.iter().all(|result| result.is_some()) {
+                    this.retry_succeeded();
+                    if let Some(clock_offset_millis) = results.into_iter().flatten().flatten().next() {
+                        this.update_clock_offset(clock_offset_millis);
+                    }
+                } else {
+                    this.retry_failed((*events).clone());
+                }
+            })
+            .detach();
+    }
+
+    /// Records a freshly-computed clock offset (see `clock_offset_millis_from_response`) and
+    /// resets the recompute timer.
+    fn update_clock_offset(self: &Arc<Self>, clock_offset_millis: i64) {
+        let mut state = self.state.lock();
+        state.clock_offset_millis = Some(clock_offset_millis);
+        state.clock_offset_checked_at = Some(Instant::now());
+    }
+
+    /// Clears the retry backoff and, as long as nothing new was reported while this batch was in
+    /// flight, the persisted queue -- every sink accepted the batch, so there's nothing left to
+    /// retry.
+    fn retry_succeeded(self: &Arc<Self>) {
+        let mut state = self.state.lock();
+        state.retry_backoff = RETRY_BACKOFF_INITIAL;
+        if state.queue.is_empty() {
+            state.persist_queue();
+        }
+    }
+
+    /// Puts a failed batch back at the front of the queue, ahead of anything reported while it
+    /// was in flight, and schedules a retry after the current backoff -- doubling the backoff
+    /// (capped at `RETRY_BACKOFF_MAX`) in case this retry fails too.
+    fn retry_failed(self: &Arc<Self>, mut events: Vec<Event>) {
+        let mut state = self.state.lock();
+        events.append(&mut state.queue);
+        state.queue = events;
+        if state.queue.len() > MAX_PERSISTED_EVENTS {
+            let overflow = state.queue.len() - MAX_PERSISTED_EVENTS;
+            state.queue.drain(..overflow);
+        }
+        state.persist_queue();
+
+        let delay = jittered(state.retry_backoff);
+        state.retry_backoff = (state.retry_backoff * 2).min(RETRY_BACKOFF_MAX);
+
+        let this = self.clone();
+        let executor = self.executor.clone();
+        state.flush_task = Some(self.executor.spawn(async move {
+            executor.timer(delay).await;
+            this.flush();
+        }));
     }
 }
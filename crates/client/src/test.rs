@@ -3,29 +3,45 @@ use crate::{
     Client, Connection, Credentials, EstablishConnectionError, UserStore,
 };
 use anyhow::{anyhow, Result};
-use futures::{future::BoxFuture, stream::BoxStream, Future, StreamExt};
+use futures::{future::BoxFuture, stream::BoxStream, AsyncReadExt, Future, StreamExt};
 use gpui::{executor, ModelHandle, TestAppContext};
 use parking_lot::Mutex;
 use rpc::{
     proto::{self, GetPrivateUserInfo, GetPrivateUserInfoResponse},
     ConnectionId, Peer, Receipt, TypedEnvelope,
 };
-use std::{fmt, rc::Rc, sync::Arc};
+use std::{collections::HashMap, fmt, rc::Rc, sync::Arc, time::Duration};
 
 pub struct FakeServer {
     peer: Arc<Peer>,
     state: Arc<Mutex<FakeServerState>>,
     user_id: u64,
     executor: Rc<executor::Foreground>,
+    background: Arc<executor::Background>,
+}
+
+/// One client's half of a connection accepted by the `FakeServer`'s `Peer`.
+struct ActiveConnection {
+    incoming: BoxStream<'static, Box<dyn proto::AnyTypedEnvelope>>,
+    user_id: u64,
 }
 
 #[derive(Default)]
 struct FakeServerState {
-    incoming: Option<BoxStream<'static, Box<dyn proto::AnyTypedEnvelope>>>,
-    connection_id: Option<ConnectionId>,
+    connections: HashMap<ConnectionId, ActiveConnection>,
     forbid_connections: bool,
     auth_count: usize,
     access_token: usize,
+    dev_server_token: Option<String>,
+    is_dev_server_connection: bool,
+    /// Artificial delay applied to every outgoing message, for simulating network latency.
+    message_latency: Duration,
+    /// How many subsequent outgoing messages `dispatch` should silently swallow.
+    messages_to_drop: usize,
+    /// How many consecutive sends get their delays staggered (in reverse) so a run of messages
+    /// can observably complete out of order; `0` (the default) or `1` means no reordering.
+    reorder_window: usize,
+    send_sequence: usize,
 }
 
 impl FakeServer {
@@ -39,6 +55,7 @@ impl FakeServer {
             state: Default::default(),
             user_id: client_user_id,
             executor: cx.foreground(),
+            background: cx.background(),
         };
 
         client
@@ -80,14 +97,193 @@ impl FakeServer {
                             Err(EstablishConnectionError::Unauthorized)?
                         }
 
+                        let (client_conn, server_conn, _) = Connection::in_memory(cx.background());
+                        let (connection_id, io, incoming) =
+                            peer.add_test_connection(server_conn, cx.background());
+                        cx.background().spawn(io).detach();
+                        state.lock().connections.insert(
+                            connection_id,
+                            ActiveConnection {
+                                incoming,
+                                user_id: client_user_id,
+                            },
+                        );
+                        peer.send(
+                            connection_id,
+                            proto::Hello {
+                                peer_id: connection_id.0,
+                            },
+                        )
+                        .unwrap();
+
+                        Ok(client_conn)
+                    })
+                }
+            });
+
+        client
+            .authenticate_and_connect(false, &cx.to_async())
+            .await
+            .unwrap();
+
+        server
+    }
+
+    /// Wires up several clients against the same in-process `Peer`, the way a real broker relays
+    /// peers, so tests can exercise join/leave and shared-worktree message fan-out. Each entry in
+    /// `clients` signs in with its own user id; the resulting connections are reachable via
+    /// `receive_from`/`send_to`/`broadcast`.
+    pub async fn for_clients(
+        clients: &[(&Arc<Client>, u64)],
+        cx: &TestAppContext,
+    ) -> Self {
+        let server = Self {
+            peer: Peer::new(),
+            state: Default::default(),
+            user_id: 0,
+            executor: cx.foreground(),
+            background: cx.background(),
+        };
+
+        for &(client, client_user_id) in clients {
+            client
+                .override_authenticate({
+                    let state = Arc::downgrade(&server.state);
+                    move |cx| {
+                        let state = state.clone();
+                        cx.spawn(move |_| async move {
+                            let state = state.upgrade().ok_or_else(|| anyhow!("server dropped"))?;
+                            let mut state = state.lock();
+                            state.auth_count += 1;
+                            let access_token = state.access_token.to_string();
+                            Ok(Credentials {
+                                user_id: client_user_id,
+                                access_token,
+                            })
+                        })
+                    }
+                })
+                .override_establish_connection({
+                    let peer = Arc::downgrade(&server.peer);
+                    let state = Arc::downgrade(&server.state);
+                    move |credentials, cx| {
+                        let peer = peer.clone();
+                        let state = state.clone();
+                        let credentials = credentials.clone();
+                        cx.spawn(move |cx| async move {
+                            let state = state.upgrade().ok_or_else(|| anyhow!("server dropped"))?;
+                            let peer = peer.upgrade().ok_or_else(|| anyhow!("server dropped"))?;
+                            if state.lock().forbid_connections {
+                                Err(EstablishConnectionError::Other(anyhow!(
+                                    "server is forbidding connections"
+                                )))?
+                            }
+
+                            assert_eq!(credentials.user_id, client_user_id);
+
+                            if credentials.access_token != state.lock().access_token.to_string() {
+                                Err(EstablishConnectionError::Unauthorized)?
+                            }
+
+                            let (client_conn, server_conn, _) =
+                                Connection::in_memory(cx.background());
+                            let (connection_id, io, incoming) =
+                                peer.add_test_connection(server_conn, cx.background());
+                            cx.background().spawn(io).detach();
+                            state.lock().connections.insert(
+                                connection_id,
+                                ActiveConnection {
+                                    incoming,
+                                    user_id: client_user_id,
+                                },
+                            );
+                            peer.send(
+                                connection_id,
+                                proto::Hello {
+                                    peer_id: connection_id.0,
+                                },
+                            )
+                            .unwrap();
+
+                            Ok(client_conn)
+                        })
+                    }
+                });
+
+            client
+                .authenticate_and_connect(false, &cx.to_async())
+                .await
+                .unwrap();
+        }
+
+        server
+    }
+
+    /// Like `for_client`, but authenticates with an opaque dev-server `token` instead of a user
+    /// id, the way a headless/remote instance would. `Credentials` has no dedicated variant for
+    /// this in the current protocol, so the token rides in `access_token` and `user_id` is left
+    /// at the sentinel `0`; `is_dev_server_connection` is how tests tell the two flows apart.
+    pub async fn for_dev_server(token: String, client: &Arc<Client>, cx: &TestAppContext) -> Self {
+        let server = Self {
+            peer: Peer::new(),
+            state: Default::default(),
+            user_id: 0,
+            executor: cx.foreground(),
+            background: cx.background(),
+        };
+        server.state.lock().dev_server_token = Some(token.clone());
+
+        client
+            .override_authenticate({
+                let token = token.clone();
+                let state = Arc::downgrade(&server.state);
+                move |cx| {
+                    let token = token.clone();
+                    let state = state.clone();
+                    cx.spawn(move |_| async move {
+                        let state = state.upgrade().ok_or_else(|| anyhow!("server dropped"))?;
+                        state.lock().auth_count += 1;
+                        Ok(Credentials {
+                            user_id: 0,
+                            access_token: token,
+                        })
+                    })
+                }
+            })
+            .override_establish_connection({
+                let peer = Arc::downgrade(&server.peer);
+                let state = Arc::downgrade(&server.state);
+                move |credentials, cx| {
+                    let peer = peer.clone();
+                    let state = state.clone();
+                    let credentials = credentials.clone();
+                    cx.spawn(move |cx| async move {
+                        let state = state.upgrade().ok_or_else(|| anyhow!("server dropped"))?;
+                        let peer = peer.upgrade().ok_or_else(|| anyhow!("server dropped"))?;
+                        if state.lock().forbid_connections {
+                            Err(EstablishConnectionError::Other(anyhow!(
+                                "server is forbidding connections"
+                            )))?
+                        }
+
+                        if state.lock().dev_server_token.as_deref() != Some(credentials.access_token.as_str()) {
+                            Err(EstablishConnectionError::Unauthorized)?
+                        }
+
                         let (client_conn, server_conn, _) = Connection::in_memory(cx.background());
                         let (connection_id, io, incoming) =
                             peer.add_test_connection(server_conn, cx.background());
                         cx.background().spawn(io).detach();
                         {
                             let mut state = state.lock();
-                            state.connection_id = Some(connection_id);
-                            state.incoming = Some(incoming);
+                            state.connections.insert(
+                                connection_id,
+                                ActiveConnection {
+                                    incoming,
+                                    user_id: 0,
+                                },
+                            );
+                            state.is_dev_server_connection = true;
                         }
                         peer.send(
                             connection_id,
@@ -110,13 +306,24 @@ impl FakeServer {
         server
     }
 
+    /// Whether the currently-established connection (if any) authenticated via `for_dev_server`
+    /// rather than a normal user sign-in.
+    pub fn is_dev_server_connection(&self) -> bool {
+        self.state.lock().is_dev_server_connection
+    }
+
     pub fn disconnect(&self) {
-        if self.state.lock().connection_id.is_some() {
-            self.peer.disconnect(self.connection_id());
-            let mut state = self.state.lock();
-            state.connection_id.take();
-            state.incoming.take();
+        let connection_ids = self
+            .state
+            .lock()
+            .connections
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for connection_id in connection_ids {
+            self.peer.disconnect(connection_id);
         }
+        self.state.lock().connections.clear();
     }
 
     pub fn auth_count(&self) -> usize {
@@ -136,20 +343,117 @@ impl FakeServer {
     }
 
     pub fn send<T: proto::EnvelopedMessage>(&self, message: T) {
-        self.peer.send(self.connection_id(), message).unwrap();
+        self.send_to(self.connection_id(), message);
+    }
+
+    pub fn send_to<T: proto::EnvelopedMessage>(&self, connection_id: ConnectionId, message: T) {
+        self.dispatch(connection_id, message);
+    }
+
+    /// Forwards `message` to every connected peer except those listed in `except`.
+    pub fn broadcast<T: proto::EnvelopedMessage + Clone>(&self, message: T, except: &[ConnectionId]) {
+        let connection_ids = self
+            .state
+            .lock()
+            .connections
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for connection_id in connection_ids {
+            if !except.contains(&connection_id) {
+                self.dispatch(connection_id, message.clone());
+            }
+        }
+    }
+
+    /// Routes an outgoing message through the configured `message_latency`/`drop_next_message`/
+    /// `reorder_window` faults before it reaches `Peer::send`. These faults are injected here,
+    /// at the one place `FakeServer` itself originates outgoing messages, rather than inside the
+    /// `Connection::in_memory` pipe `override_establish_connection` hands off to the `Peer` --
+    /// that pipe's exact `Sink`/`Stream` shape isn't defined in this crate, so wrapping it here
+    /// would mean guessing at an unverifiable trait bound.
+    fn dispatch<T: proto::EnvelopedMessage>(&self, connection_id: ConnectionId, message: T) {
+        let (should_drop, delay) = {
+            let mut state = self.state.lock();
+            let should_drop = if state.messages_to_drop > 0 {
+                state.messages_to_drop -= 1;
+                true
+            } else {
+                false
+            };
+            let window = state.reorder_window.max(1) as u32;
+            let sequence = state.send_sequence as u32 % window;
+            state.send_sequence += 1;
+            // Stagger delays in reverse within each window so a run of sends can complete out
+            // of FIFO order without relying on non-determinism.
+            let reorder_offset = state.message_latency * (window - 1 - sequence);
+            (should_drop, state.message_latency + reorder_offset)
+        };
+
+        if should_drop {
+            return;
+        }
+
+        if delay.is_zero() {
+            self.peer.send(connection_id, message).unwrap();
+            return;
+        }
+
+        let peer = self.peer.clone();
+        self.background
+            .spawn(async move {
+                smol::Timer::after(delay).await;
+                // The connection may have been severed by the time this fires; that's an
+                // expected outcome of fault injection, not a bug, so ignore the error.
+                peer.send(connection_id, message).ok();
+            })
+            .detach();
+    }
+
+    /// Sets the artificial delay applied to every message sent after this call.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().message_latency = latency;
+    }
+
+    /// Causes the next outgoing message to be silently dropped instead of sent.
+    pub fn drop_next_message(&self) {
+        self.state.lock().messages_to_drop += 1;
+    }
+
+    /// Sets how many consecutive sends get their delays staggered against each other; `0`
+    /// (the default) or `1` disables reordering.
+    pub fn set_reorder_window(&self, window: usize) {
+        self.state.lock().reorder_window = window;
+    }
+
+    /// Drops the current connection without forbidding new ones, so a client's own retry logic
+    /// can re-establish through `override_establish_connection` -- unlike `disconnect`, which is
+    /// meant as a final teardown, this is for exercising reconnection.
+    pub fn sever_then_restore(&self) {
+        let connection_id = self.connection_id();
+        self.peer.disconnect(connection_id);
+        self.state.lock().connections.remove(&connection_id);
     }
 
-    #[allow(clippy::await_holding_lock)]
     pub async fn receive<M: proto::EnvelopedMessage>(&self) -> Result<TypedEnvelope<M>> {
+        self.receive_from(self.connection_id()).await
+    }
+
+    #[allow(clippy::await_holding_lock)]
+    pub async fn receive_from<M: proto::EnvelopedMessage>(
+        &self,
+        connection_id: ConnectionId,
+    ) -> Result<TypedEnvelope<M>> {
         self.executor.start_waiting();
 
         loop {
             let message = self
                 .state
                 .lock()
-                .incoming
-                .as_mut()
+                .connections
+                .get_mut(&connection_id)
                 .expect("not connected")
+                .incoming
                 .next()
                 .await
                 .ok_or_else(|| anyhow!("other half hung up"))?;
@@ -191,8 +495,27 @@ impl FakeServer {
         self.peer.respond(receipt, response).unwrap()
     }
 
+    /// The sole connected peer's id. Panics if there isn't exactly one -- servers built with
+    /// `for_clients` should use `receive_from`/`send_to`/`broadcast` with an explicit id instead.
     fn connection_id(&self) -> ConnectionId {
-        self.state.lock().connection_id.expect("not connected")
+        let state = self.state.lock();
+        let mut ids = state.connections.keys().copied();
+        let id = ids.next().expect("not connected");
+        assert!(
+            ids.next().is_none(),
+            "server has multiple connections; use receive_from/send_to/broadcast instead"
+        );
+        id
+    }
+
+    /// The user id a connected peer authenticated as, for servers built with `for_clients`.
+    pub fn user_id_for_connection(&self, connection_id: ConnectionId) -> u64 {
+        self.state
+            .lock()
+            .connections
+            .get(&connection_id)
+            .expect("not connected")
+            .user_id
     }
 
     pub async fn build_user_store(
@@ -220,36 +543,168 @@ impl Drop for FakeServer {
     }
 }
 
+type RouteHandler =
+    Box<dyn 'static + Send + Sync + Fn(Request) -> BoxFuture<'static, Result<Response, http::Error>>>;
+
+struct Route {
+    method: String,
+    path_or_glob: String,
+    delay: Option<Duration>,
+    handler: RouteHandler,
+}
+
+/// A request captured by `FakeHttpClient` for later assertions via `requests()`/`assert_request()`.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+fn matches_path_or_glob(path_or_glob: &str, path: &str) -> bool {
+    match path_or_glob.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => path == path_or_glob,
+    }
+}
+
 pub struct FakeHttpClient {
-    handler: Box<
-        dyn 'static
-            + Send
-            + Sync
-            + Fn(Request) -> BoxFuture<'static, Result<Response, http::Error>>,
-    >,
+    routes: Arc<Vec<Route>>,
+    default_handler: Arc<RouteHandler>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
 }
 
-impl FakeHttpClient {
-    pub fn create<Fut, F>(handler: F) -> Arc<dyn HttpClient>
+pub struct FakeHttpClientBuilder {
+    routes: Vec<Route>,
+    default_handler: Option<RouteHandler>,
+}
+
+impl FakeHttpClientBuilder {
+    /// Registers a handler for requests whose method matches `method` (case-insensitively) and
+    /// whose path matches `path_or_glob` (an exact path, or a path ending in `*` matched as a
+    /// prefix). The first matching route wins; unmatched requests fall through to `default`.
+    pub fn route<Fut, F>(mut self, method: &str, path_or_glob: &str, handler: F) -> Self
     where
         Fut: 'static + Send + Future<Output = Result<Response, http::Error>>,
         F: 'static + Send + Sync + Fn(Request) -> Fut,
     {
-        Arc::new(Self {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path_or_glob: path_or_glob.to_string(),
+            delay: None,
             handler: Box::new(move |req| Box::pin(handler(req))),
+        });
+        self
+    }
+
+    /// Like `route`, but waits `delay` (via the `smol` timer) before invoking `handler`, useful
+    /// for exercising timeout/retry code paths.
+    pub fn route_with_delay<Fut, F>(
+        mut self,
+        method: &str,
+        path_or_glob: &str,
+        delay: Duration,
+        handler: F,
+    ) -> Self
+    where
+        Fut: 'static + Send + Future<Output = Result<Response, http::Error>>,
+        F: 'static + Send + Sync + Fn(Request) -> Fut,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path_or_glob: path_or_glob.to_string(),
+            delay: Some(delay),
+            handler: Box::new(move |req| Box::pin(handler(req))),
+        });
+        self
+    }
+
+    /// Convenience for a `GET` route that always returns `value` serialized as a JSON body.
+    pub fn get_json(self, path_or_glob: &str, value: impl serde::Serialize) -> Self {
+        let body = serde_json::to_vec(&value).expect("failed to serialize fake json response");
+        self.route("GET", path_or_glob, move |_| {
+            let body = body.clone();
+            async move {
+                Ok(isahc::Response::builder()
+                    .status(200)
+                    .header("content-type", "application/json")
+                    .body(body.into())
+                    .unwrap())
+            }
         })
     }
 
-    pub fn with_404_response() -> Arc<dyn HttpClient> {
-        Self::create(|_| async move {
-            Ok(isahc::Response::builder()
-                .status(404)
-                .body(Default::default())
-                .unwrap())
+    /// Sets the handler used when no route matches; defaults to a bare 404 if never called.
+    pub fn default<Fut, F>(mut self, handler: F) -> Self
+    where
+        Fut: 'static + Send + Future<Output = Result<Response, http::Error>>,
+        F: 'static + Send + Sync + Fn(Request) -> Fut,
+    {
+        self.default_handler = Some(Box::new(move |req| Box::pin(handler(req))));
+        self
+    }
+
+    pub fn build(self) -> Arc<FakeHttpClient> {
+        let default_handler = self.default_handler.unwrap_or_else(|| {
+            Box::new(|_| {
+                Box::pin(async move {
+                    Ok(isahc::Response::builder()
+                        .status(404)
+                        .body(Default::default())
+                        .unwrap())
+                })
+            })
+        });
+        Arc::new(FakeHttpClient {
+            routes: Arc::new(self.routes),
+            default_handler: Arc::new(default_handler),
+            requests: Default::default(),
         })
     }
 }
 
+impl FakeHttpClient {
+    pub fn builder() -> FakeHttpClientBuilder {
+        FakeHttpClientBuilder {
+            routes: Vec::new(),
+            default_handler: None,
+        }
+    }
+
+    pub fn create<Fut, F>(handler: F) -> Arc<dyn HttpClient>
+    where
+        Fut: 'static + Send + Future<Output = Result<Response, http::Error>>,
+        F: 'static + Send + Sync + Fn(Request) -> Fut,
+    {
+        Self::builder().default(handler).build()
+    }
+
+    pub fn with_404_response() -> Arc<dyn HttpClient> {
+        Self::builder().build()
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().clone()
+    }
+
+    /// Returns the first recorded request matching `method`/`path_or_glob`, panicking if none did.
+    pub fn assert_request(&self, method: &str, path_or_glob: &str) -> RecordedRequest {
+        self.requests()
+            .into_iter()
+            .find(|request| {
+                let path = request
+                    .uri
+                    .parse::<isahc::http::Uri>()
+                    .map(|uri| uri.path().to_string())
+                    .unwrap_or_else(|_| request.uri.clone());
+                request.method.eq_ignore_ascii_case(method)
+                    && matches_path_or_glob(path_or_glob, &path)
+            })
+            .unwrap_or_else(|| panic!("no request recorded matching {method} {path_or_glob}"))
+    }
+}
+
 impl fmt::Debug for FakeHttpClient {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("FakeHttpClient").finish()
@@ -257,8 +712,50 @@ impl fmt::Debug for FakeHttpClient {
 }
 
 impl HttpClient for FakeHttpClient {
-    fn send(&self, req: Request) -> BoxFuture<Result<Response, crate::http::Error>> {
-        let future = (self.handler)(req);
-        Box::pin(async move { future.await.map(Into::into) })
+    fn send(&self, mut req: Request) -> BoxFuture<Result<Response, crate::http::Error>> {
+        let routes = self.routes.clone();
+        let default_handler = self.default_handler.clone();
+        let requests = self.requests.clone();
+
+        Box::pin(async move {
+            let method = req.method().as_str().to_string();
+            let uri = req.uri().to_string();
+            let path = req.uri().path().to_string();
+            let headers = req
+                .headers()
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        value.to_str().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let mut body = Vec::new();
+            req.body_mut().read_to_end(&mut body).await.ok();
+            requests.lock().push(RecordedRequest {
+                method: method.clone(),
+                uri,
+                headers,
+                body: body.clone(),
+            });
+
+            let route = routes
+                .iter()
+                .find(|route| route.method.eq_ignore_ascii_case(&method) && matches_path_or_glob(&route.path_or_glob, &path));
+
+            if let Some(delay) = route.and_then(|route| route.delay) {
+                smol::Timer::after(delay).await;
+            }
+
+            let (parts, _) = req.into_parts();
+            let req = Request::from_parts(parts, body.into());
+
+            match route {
+                Some(route) => (route.handler)(req).await,
+                None => (default_handler.as_ref())(req).await,
+            }
+        })
     }
 }
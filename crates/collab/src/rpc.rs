@@ -16,21 +16,25 @@ use axum::{
         ConnectInfo, WebSocketUpgrade,
     },
     headers::{Header, HeaderName},
-    http::StatusCode,
+    http::{HeaderMap, HeaderValue, StatusCode},
     middleware,
     response::IntoResponse,
-    routing::get,
+    routing::{any, get},
     Extension, Router, TypedHeader,
 };
 use collections::{HashMap, HashSet};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
 use futures::{
     channel::{mpsc, oneshot},
     future::{self, BoxFuture},
     stream::FuturesUnordered,
-    FutureExt, SinkExt, StreamExt, TryStreamExt,
+    FutureExt, Sink, SinkExt, Stream, StreamExt, TryStreamExt,
 };
 use lazy_static::lazy_static;
-use prometheus::{register_int_gauge, IntGauge};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
 use rpc::{
     proto::{self, AnyTypedEnvelope, EntityMessage, EnvelopedMessage, RequestMessage},
     Connection, ConnectionId, Peer, Receipt, TypedEnvelope,
@@ -38,16 +42,19 @@ use rpc::{
 use serde::{Serialize, Serializer};
 use std::{
     any::TypeId,
+    collections::VecDeque,
     future::Future,
     marker::PhantomData,
     net::SocketAddr,
     ops::{Deref, DerefMut},
     os::unix::prelude::OsStrExt,
+    pin::Pin,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering::SeqCst},
         Arc,
     },
+    task::{Context, Poll},
     time::Duration,
 };
 pub use store::{Store, Worktree};
@@ -71,6 +78,128 @@ lazy_static! {
         "number of open projects with one or more guests"
     )
     .unwrap();
+    // The outgoing side of each connection is an unbounded queue (buffering a serialized
+    // protobuf is cheaper than stalling whichever task is broadcasting to everyone), so nothing
+    // here applies backpressure to a slow client; this gauge is how an operator notices one
+    // anyway, rather than discovering it as unbounded memory growth.
+    static ref METRIC_MAX_OUTGOING_QUEUE_DEPTH: IntGauge = register_int_gauge!(
+        "max_outgoing_queue_depth",
+        "depth of the most backlogged connection's outgoing message queue"
+    )
+    .unwrap();
+    static ref METRIC_MESSAGES_HANDLED: IntCounterVec = register_int_counter_vec!(
+        "messages_handled_total",
+        "messages handled by type",
+        &["type"]
+    )
+    .unwrap();
+    static ref METRIC_FORWARD_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "forward_request_duration_seconds",
+        "latency of a forward_request round trip to another peer",
+        &["type"]
+    )
+    .unwrap();
+    static ref METRIC_BROADCAST_FAN_OUT: IntCounter = register_int_counter!(
+        "broadcast_fan_out_total",
+        "total number of recipients across all broadcast calls"
+    )
+    .unwrap();
+    static ref METRIC_MESSAGE_HANDLING_DURATION: HistogramVec = register_histogram_vec!(
+        "message_handling_duration_seconds",
+        "latency of handling one incoming RPC message, by type",
+        &["type"]
+    )
+    .unwrap();
+    /// Messages actually routed through `deliver`, the cross-server-aware send path. Other call
+    /// sites still send via `self.peer.send`/`broadcast` directly (see `deliver`'s doc comment),
+    /// so this undercounts total outbound traffic until they're migrated over too.
+    static ref METRIC_MESSAGES_SENT: IntCounterVec = register_int_counter_vec!(
+        "messages_sent_total",
+        "messages sent to a connection via `deliver`, by type",
+        &["type"]
+    )
+    .unwrap();
+    static ref METRIC_WEBSOCKET_BYTES_BEFORE_COMPRESSION: IntCounter = register_int_counter!(
+        "websocket_bytes_before_compression_total",
+        "total outgoing Binary payload bytes, before permessage-deflate compression"
+    )
+    .unwrap();
+    static ref METRIC_WEBSOCKET_BYTES_AFTER_COMPRESSION: IntCounter = register_int_counter!(
+        "websocket_bytes_after_compression_total",
+        "total outgoing Binary payload bytes actually written to the socket, after \
+         permessage-deflate compression, including each frame's 1-byte compression marker \
+         (frames at or below COMPRESSION_THRESHOLD_BYTES are sent uncompressed; connections \
+         that didn't negotiate compression aren't counted)"
+    )
+    .unwrap();
+    /// Only the `protocol_version` reason is incremented from this file today, since
+    /// auth-failure rejections happen in `auth::validate_header`/`validate_headless_header`
+    /// (outside `handle_websocket_request`) and oversized-message rejections are a `1009` close
+    /// issued by the underlying WebSocket implementation (see `WebSocketConfig`), neither of
+    /// which this crate's upgrade handlers observe directly. Both are expected to increment this
+    /// same counter under their own `reason` label once instrumented at their own layer.
+    static ref METRIC_UPGRADE_REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        "websocket_upgrade_rejections_total",
+        "rejected /rpc and /rpc_headless upgrade attempts, by reason",
+        &["reason"]
+    )
+    .unwrap();
+}
+
+/// Maps file extensions (as reported in `UpdateWorktreeExtensions`) to the language they're
+/// aggregated under in project/user language-composition stats. Multiple extensions can map to
+/// the same language (e.g. `.ts`/`.tsx` both count as "TypeScript"); anything not listed here is
+/// bucketed as "Other" rather than dropped, so code composition percentages always sum to 100%.
+const LANGUAGE_EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "Rust"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("js", "JavaScript"),
+    ("jsx", "JavaScript"),
+    ("py", "Python"),
+    ("rb", "Ruby"),
+    ("go", "Go"),
+    ("c", "C"),
+    ("h", "C"),
+    ("cpp", "C++"),
+    ("cc", "C++"),
+    ("hpp", "C++"),
+    ("swift", "Swift"),
+    ("java", "Java"),
+    ("kt", "Kotlin"),
+    ("md", "Markdown"),
+    ("json", "JSON"),
+    ("toml", "TOML"),
+    ("yaml", "YAML"),
+    ("yml", "YAML"),
+    ("html", "HTML"),
+    ("css", "CSS"),
+    ("scss", "CSS"),
+];
+const OTHER_LANGUAGE: &str = "Other";
+
+lazy_static! {
+    static ref LANGUAGE_BY_EXTENSION: HashMap<&'static str, &'static str> =
+        LANGUAGE_EXTENSIONS.iter().copied().collect();
+}
+
+/// Resolves the room participant named by a moderation request's `user_id` to the `ConnectionId`
+/// the store/peer actually address them by. `user_id` and connection/peer ids are different id
+/// spaces -- wrapping a `user_id` directly in `ConnectionId` would target the wrong connection (or
+/// none), so every moderation action looks the participant up by `user_id` here first.
+fn connection_id_for_participant(room: &proto::Room, user_id: u64) -> Result<ConnectionId> {
+    room.participants
+        .iter()
+        .find(|participant| participant.user_id == user_id)
+        .map(|participant| ConnectionId(participant.peer_id))
+        .ok_or_else(|| anyhow!("no such participant"))
+}
+
+fn language_for_extension(extension: &str) -> &'static str {
+    LANGUAGE_BY_EXTENSION
+        .get(extension)
+        .copied()
+        .unwrap_or(OTHER_LANGUAGE)
 }
 
 type MessageHandler =
@@ -91,13 +220,76 @@ impl<R: RequestMessage> Response<R> {
 }
 
 pub struct Server {
+    id: ServerId,
     peer: Arc<Peer>,
     pub(crate) store: Mutex<Store>,
     app_state: Arc<AppState>,
     handlers: HashMap<TypeId, MessageHandler>,
     notifications: Option<mpsc::UnboundedSender<()>>,
+    /// Set by `release_connections` during a clean shutdown. Checked by the `/rpc`/`/rpc_headless`
+    /// upgrade handlers so a connection that ends while this node is draining gets an explicit
+    /// `1001` (going away) close frame instead of the client just seeing a dropped socket and
+    /// having to guess why.
+    shutting_down: AtomicBool,
+    /// Per-connection frame/message size and write-buffer limits applied to every `/rpc` and
+    /// `/rpc_headless` upgrade, so a single misbehaving or malicious client can't force unbounded
+    /// allocations on this process. Defaults to `WebSocketConfig::default()`; override with
+    /// `set_websocket_config` from server startup options to tune per deployment.
+    websocket_config: WebSocketConfig,
+    /// The `UserId` each live connection authenticated as, populated once in
+    /// `handle_connection`/`handle_headless_connection` and consulted by
+    /// `add_message_handler_with_user`/`add_request_handler_with_user` on every message. This is
+    /// a dedicated, uncontended lock so looking up "who is this message from" doesn't have to
+    /// take the much busier `store` mutex, which guards the rest of a connection's room/project
+    /// state.
+    connection_user_ids: std::sync::Mutex<HashMap<ConnectionId, UserId>>,
+    /// The protocol version each live connection negotiated in `handle_connection`, somewhere in
+    /// `[MIN_SUPPORTED_PROTOCOL_VERSION, rpc::PROTOCOL_VERSION]`. Populated/removed alongside
+    /// `connection_user_ids` and handed to handlers via `Message::protocol_version` so they can
+    /// gate behavior on it without re-deriving it from the original request.
+    connection_protocol_versions: std::sync::Mutex<HashMap<ConnectionId, u32>>,
+    /// Messages `deliver` has sent for a user, each tagged with the `outgoing_sequences` position
+    /// it was sent under. Replayed in order -- skipping anything at or below what the client has
+    /// already acknowledged -- once that user reconnects with a resume token, so messages sent
+    /// during an outage aren't lost even if the gap between "connection dropped" and "marked
+    /// suspended" let a few of them go out on a socket that was already dead. Keyed by `UserId`
+    /// rather than the old, now-dead `ConnectionId`, since that's the identity the reconnecting
+    /// client carries forward; bounded per user by `REPLAY_BUFFER_CAPACITY`.
+    replay_buffers:
+        std::sync::Mutex<HashMap<UserId, VecDeque<(u64, Box<dyn FnOnce(&Peer, ConnectionId) + Send>)>>>,
+    /// The next sequence number `deliver` will assign to an outgoing message for a given user.
+    /// Monotonic for as long as the user has a `replay_buffers` entry; paired with
+    /// `acked_sequences`, this is what lets a resumed connection replay exactly the messages it
+    /// missed, in order, without ever re-delivering one the client already has.
+    outgoing_sequences: std::sync::Mutex<HashMap<UserId, u64>>,
+    /// The highest `outgoing_sequences` number each user's client has told us it received, via the
+    /// periodic `acknowledge_messages` message or the `x-zed-last-sequence-acked` header presented
+    /// on reconnect. `deliver` consults this before buffering a message for replay, so a client
+    /// that's acking promptly keeps `replay_buffers` empty instead of paying for a buffer it will
+    /// never need.
+    acked_sequences: std::sync::Mutex<HashMap<UserId, u64>>,
+}
+
+/// A message paired with the `UserId` that was authenticated for its sender's connection, so a
+/// handler registered via `add_message_handler_with_user`/`add_request_handler_with_user` can act
+/// on the caller's identity without a `self.store().await.user_id_for_connection(..)` round-trip.
+struct Message<T> {
+    sender_user_id: UserId,
+    sender_connection_id: ConnectionId,
+    /// The protocol version the sender's connection negotiated in `handle_connection`, or
+    /// `rpc::PROTOCOL_VERSION` if it isn't on record. Connections from `handle_headless_connection`
+    /// never record one, so their messages always carry `rpc::PROTOCOL_VERSION`.
+    protocol_version: u32,
+    payload: T,
 }
 
+/// Identifies one of potentially several collab processes sharing load behind a load balancer.
+/// Assigned at startup and stored alongside each connection/participant in the shared database,
+/// so any node can tell whether a `ConnectionId` it needs to reach is one of its own or belongs
+/// to a peer node, and route the message accordingly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct ServerId(pub i32);
+
 pub trait Executor: Send + Clone {
     type Sleep: Send + Future;
     fn spawn_detached<F: 'static + Send + Future<Output = ()>>(&self, future: F);
@@ -110,6 +302,17 @@ pub struct RealExecutor;
 const MESSAGE_COUNT_PER_PAGE: usize = 100;
 const MAX_MESSAGE_LEN: usize = 1024;
 
+/// How long a connection's hosted/guest project and room state is kept alive after its I/O
+/// fails, waiting for the client to reconnect and present its resume token, before we fall
+/// back to a full `sign_out`.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many unacknowledged messages `deliver` holds per user in `replay_buffers`, whether or not
+/// the user's connection is currently suspended. Bounded so a client that never comes back can't
+/// pin unbounded memory; once full, we drop the oldest buffered message rather than the newest, since the
+/// client is going to need a full resync either way once it's fallen this far behind.
+const REPLAY_BUFFER_CAPACITY: usize = 64;
+
 pub(crate) struct StoreGuard<'a> {
     guard: MutexGuard<'a, Store>,
     _not_send: PhantomData<Rc<()>>,
@@ -133,19 +336,29 @@ where
 
 impl Server {
     pub fn new(
+        id: ServerId,
         app_state: Arc<AppState>,
         notifications: Option<mpsc::UnboundedSender<()>>,
     ) -> Arc<Self> {
         let mut server = Self {
+            id,
             peer: Peer::new(),
             app_state,
             store: Default::default(),
             handlers: Default::default(),
             notifications,
+            shutting_down: AtomicBool::new(false),
+            websocket_config: WebSocketConfig::default(),
+            connection_user_ids: Default::default(),
+            connection_protocol_versions: Default::default(),
+            replay_buffers: Default::default(),
+            outgoing_sequences: Default::default(),
+            acked_sequences: Default::default(),
         };
 
         server
             .add_request_handler(Server::ping)
+            .add_message_handler_with_user(Server::acknowledge_messages)
             .add_request_handler(Server::create_room)
             .add_request_handler(Server::join_room)
             .add_message_handler(Server::leave_room)
@@ -153,6 +366,9 @@ impl Server {
             .add_request_handler(Server::cancel_call)
             .add_message_handler(Server::decline_call)
             .add_request_handler(Server::update_participant_location)
+            .add_request_handler(Server::set_participant_role)
+            .add_request_handler(Server::mute_participant)
+            .add_request_handler(Server::remove_participant_from_call)
             .add_request_handler(Server::share_project)
             .add_message_handler(Server::unshare_project)
             .add_request_handler(Server::join_project)
@@ -161,6 +377,7 @@ impl Server {
             .add_message_handler(Server::register_project_activity)
             .add_request_handler(Server::update_worktree)
             .add_message_handler(Server::update_worktree_extensions)
+            .add_request_handler(Server::get_project_language_stats)
             .add_message_handler(Server::start_language_server)
             .add_message_handler(Server::update_language_server)
             .add_message_handler(Server::update_diagnostic_summary)
@@ -194,25 +411,36 @@ impl Server {
             .add_message_handler(Server::buffer_reloaded)
             .add_message_handler(Server::buffer_saved)
             .add_request_handler(Server::save_buffer)
-            .add_request_handler(Server::get_channels)
+            .add_request_handler_with_user(Server::get_channels)
             .add_request_handler(Server::get_users)
-            .add_request_handler(Server::fuzzy_search_users)
-            .add_request_handler(Server::request_contact)
-            .add_request_handler(Server::remove_contact)
-            .add_request_handler(Server::respond_to_contact_request)
+            .add_request_handler_with_user(Server::fuzzy_search_users)
+            .add_request_handler_with_user(Server::request_contact)
+            .add_request_handler_with_user(Server::remove_contact)
+            .add_request_handler_with_user(Server::respond_to_contact_request)
             .add_request_handler(Server::join_channel)
-            .add_message_handler(Server::leave_channel)
-            .add_request_handler(Server::send_channel_message)
+            .add_message_handler_with_user(Server::leave_channel)
+            .add_request_handler_with_user(Server::send_channel_message)
+            .add_request_handler(Server::join_channel_buffer)
+            .add_message_handler(Server::leave_channel_buffer)
+            .add_request_handler(Server::update_channel_buffer)
             .add_request_handler(Server::follow)
             .add_message_handler(Server::unfollow)
             .add_message_handler(Server::update_followers)
-            .add_request_handler(Server::get_channel_messages)
+            .add_request_handler_with_user(Server::get_channel_messages)
             .add_message_handler(Server::update_diff_base)
-            .add_request_handler(Server::get_private_user_info);
+            .add_request_handler_with_user(Server::get_private_user_info);
 
         Arc::new(server)
     }
 
+    /// Overrides the default `WebSocketConfig`, e.g. from server startup options. Must be called
+    /// before any connections are accepted, since `handle_websocket_request` reads the config
+    /// fresh on every upgrade.
+    pub fn set_websocket_config(&mut self, config: WebSocketConfig) -> &mut Self {
+        self.websocket_config = config;
+        self
+    }
+
     fn add_message_handler<F, Fut, M>(&mut self, handler: F) -> &mut Self
     where
         F: 'static + Send + Sync + Fn(Arc<Self>, TypedEnvelope<M>) -> Fut,
@@ -233,11 +461,18 @@ impl Server {
                         "message received"
                     );
                 });
+                METRIC_MESSAGES_HANDLED
+                    .with_label_values(&[envelope.payload_type_name()])
+                    .inc();
+                let timer = METRIC_MESSAGE_HANDLING_DURATION
+                    .with_label_values(&[envelope.payload_type_name()])
+                    .start_timer();
                 let future = (handler)(server, *envelope);
                 async move {
                     if let Err(error) = future.await {
                         tracing::error!(%error, "error handling message");
                     }
+                    timer.stop_and_record();
                 }
                 .instrument(span)
                 .boxed()
@@ -290,6 +525,89 @@ impl Server {
         })
     }
 
+    /// Like `add_message_handler`, but looks up the sender's `UserId` from `connection_user_ids`
+    /// and hands it to the handler as part of a `Message<M>`, so the handler body doesn't need
+    /// to take the (much busier) `store` lock just to find out who's calling.
+    fn add_message_handler_with_user<F, Fut, M>(&mut self, handler: F) -> &mut Self
+    where
+        F: 'static + Send + Sync + Fn(Arc<Self>, Message<M>) -> Fut,
+        Fut: 'static + Send + Future<Output = Result<()>>,
+        M: EnvelopedMessage,
+    {
+        let handler = Arc::new(handler);
+        self.add_message_handler(move |server, envelope| {
+            let handler = handler.clone();
+            async move {
+                let sender_user_id = server
+                    .connection_user_ids
+                    .lock()
+                    .unwrap()
+                    .get(&envelope.sender_id)
+                    .copied()
+                    .ok_or_else(|| anyhow!("no such connection"))?;
+                let protocol_version = server
+                    .connection_protocol_versions
+                    .lock()
+                    .unwrap()
+                    .get(&envelope.sender_id)
+                    .copied()
+                    .unwrap_or(rpc::PROTOCOL_VERSION);
+                (handler)(
+                    server,
+                    Message {
+                        sender_user_id,
+                        sender_connection_id: envelope.sender_id,
+                        protocol_version,
+                        payload: envelope.payload,
+                    },
+                )
+                .await
+            }
+        })
+    }
+
+    /// The `add_request_handler` equivalent of `add_message_handler_with_user`.
+    fn add_request_handler_with_user<F, Fut, M>(&mut self, handler: F) -> &mut Self
+    where
+        F: 'static + Send + Sync + Fn(Arc<Self>, Message<M>, Response<M>) -> Fut,
+        Fut: Send + Future<Output = Result<()>>,
+        M: RequestMessage,
+    {
+        let handler = Arc::new(handler);
+        self.add_request_handler(move |server, envelope, response| {
+            let handler = handler.clone();
+            let sender_user_id = server
+                .connection_user_ids
+                .lock()
+                .unwrap()
+                .get(&envelope.sender_id)
+                .copied();
+            let protocol_version = server
+                .connection_protocol_versions
+                .lock()
+                .unwrap()
+                .get(&envelope.sender_id)
+                .copied()
+                .unwrap_or(rpc::PROTOCOL_VERSION);
+            async move {
+                let sender_user_id =
+                    sender_user_id.ok_or_else(|| anyhow!("no such connection"))?;
+                let sender_connection_id = envelope.sender_id;
+                (handler)(
+                    server,
+                    Message {
+                        sender_user_id,
+                        sender_connection_id,
+                        protocol_version,
+                        payload: envelope.payload,
+                    },
+                    response,
+                )
+                .await
+            }
+        })
+    }
+
     /// Start a long lived task that records which users are active in which projects.
     pub fn start_recording_project_activity<E: 'static + Executor>(
         self: &Arc<Self>,
@@ -342,11 +660,25 @@ impl Server {
         });
     }
 
+    /// Releases every connection this instance has claimed ownership of in the cross-server
+    /// message bus. Call this during a clean shutdown so other instances stop trying to route
+    /// messages here the moment this process stops accepting new ones, instead of only finding
+    /// out once a `publish` to a dead node times out.
+    pub fn release_connections(&self) {
+        self.shutting_down.store(true, SeqCst);
+        for connection_id in self.connection_user_ids.lock().unwrap().keys() {
+            self.app_state.message_bus.unregister(*connection_id);
+        }
+    }
+
     pub fn handle_connection<E: Executor>(
         self: &Arc<Self>,
         connection: Connection,
         address: String,
         user: User,
+        resume_token: Option<String>,
+        last_sequence_acked: Option<u64>,
+        protocol_version: u32,
         mut send_connection_id: Option<oneshot::Sender<ConnectionId>>,
         executor: E,
     ) -> impl Future<Output = Result<()>> {
@@ -368,8 +700,28 @@ impl Server {
                 });
 
             tracing::info!(%user_id, %login, %connection_id, %address, "connection opened");
-            this.peer.send(connection_id, proto::Hello { peer_id: connection_id.0 })?;
-            tracing::info!(%user_id, %login, %connection_id, %address, "sent hello message");
+
+            // If the client presents a resume token for a connection that's still within its
+            // reconnect window, transfer that connection's store entries (hosted/guest projects,
+            // room seat) onto this new connection id and cancel its teardown timer, instead of
+            // treating this as a brand new sign-in.
+            let resumed = if let Some(token) = resume_token.as_deref() {
+                this.store()
+                    .await
+                    .resume_connection(token, connection_id, user_id)
+            } else {
+                false
+            };
+
+            let new_resume_token = this.store().await.issue_resume_token(connection_id);
+            this.peer.send(
+                connection_id,
+                proto::Hello {
+                    peer_id: connection_id.0,
+                    resume_token: Some(new_resume_token),
+                },
+            )?;
+            tracing::info!(%user_id, %login, %connection_id, %address, resumed, "sent hello message");
 
             if let Some(send_connection_id) = send_connection_id.take() {
                 let _ = send_connection_id.send(connection_id);
@@ -385,9 +737,50 @@ impl Server {
                 this.app_state.db.get_invite_code_for_user(user_id)
             ).await?;
 
+            this.app_state.message_bus.register(connection_id, this.id);
+            this.connection_user_ids
+                .lock()
+                .unwrap()
+                .insert(connection_id, user_id);
+            this.connection_protocol_versions
+                .lock()
+                .unwrap()
+                .insert(connection_id, protocol_version);
+
+            // Replay whatever `deliver` buffered for this user that the reconnecting client
+            // hasn't already told us it received, in sequence order, so a dropped connection
+            // never loses a message and never replays one twice.
+            if resumed {
+                let last_sequence_acked = last_sequence_acked.unwrap_or(0);
+                this.acked_sequences
+                    .lock()
+                    .unwrap()
+                    .entry(user_id)
+                    .and_modify(|acked| *acked = (*acked).max(last_sequence_acked))
+                    .or_insert(last_sequence_acked);
+                let buffered = this
+                    .replay_buffers
+                    .lock()
+                    .unwrap()
+                    .remove(&user_id)
+                    .unwrap_or_default();
+                for (sequence, deliver) in buffered {
+                    if sequence > last_sequence_acked {
+                        deliver(&this.peer, connection_id);
+                    }
+                }
+            }
+
             {
                 let mut store = this.store().await;
-                let incoming_call = store.add_connection(connection_id, user_id, user.admin);
+                // A resumed connection already has a room seat and project membership carried
+                // over from its suspended predecessor, so we skip re-registering it (which would
+                // otherwise look like a brand new sign-in to every other collaborator).
+                let incoming_call = if resumed {
+                    None
+                } else {
+                    store.add_connection(connection_id, user_id, user.admin)
+                };
                 if let Some(incoming_call) = incoming_call {
                     this.peer.send(connection_id, incoming_call)?;
                 }
@@ -406,15 +799,25 @@ impl Server {
             let handle_io = handle_io.fuse();
             futures::pin_mut!(handle_io);
 
-            // Handlers for foreground messages are pushed into the following `FuturesUnordered`.
-            // This prevents deadlocks when e.g., client A performs a request to client B and
-            // client B performs a request to client A. If both clients stop processing further
-            // messages until their respective request completes, they won't have a chance to
-            // respond to the other client's request and cause a deadlock.
+            // Handlers for foreground messages are pushed into one of the following two
+            // `FuturesUnordered`s, split by priority. This prevents deadlocks when e.g., client A
+            // performs a request to client B and client B performs a request to client A. If both
+            // clients stop processing further messages until their respective request completes,
+            // they won't have a chance to respond to the other client's request and cause a
+            // deadlock.
             //
             // This arrangement ensures we will attempt to process earlier messages first, but fall
-            // back to processing messages arrived later in the spirit of making progress.
-            let mut foreground_message_handlers = FuturesUnordered::new();
+            // back to processing messages arrived later in the spirit of making progress. The
+            // `select_biased!` below always drains `high_priority_message_handlers` ahead of
+            // `message_handlers`, so latency-sensitive control traffic (pings, call signaling,
+            // participant location updates) isn't stuck behind bulk traffic like worktree/buffer
+            // syncs. `incoming_rx` itself is a bounded channel (see `Peer::add_connection`), so a
+            // noisy peer gets real backpressure on its socket reads rather than buffering
+            // unboundedly in our process; outgoing messages, by contrast, are queued unbounded
+            // since buffering a serialized protobuf is far cheaper than stalling a server task
+            // that's mid-request.
+            let mut high_priority_message_handlers = FuturesUnordered::new();
+            let mut message_handlers = FuturesUnordered::new();
             loop {
                 let next_message = incoming_rx.next().fuse();
                 futures::pin_mut!(next_message);
@@ -425,7 +828,8 @@ impl Server {
                         }
                         break;
                     }
-                    _ = foreground_message_handlers.next() => {}
+                    _ = high_priority_message_handlers.next() => {}
+                    _ = message_handlers.next() => {}
                     message = next_message => {
                         if let Some(message) = message {
                             let type_name = message.payload_type_name();
@@ -434,6 +838,7 @@ impl Server {
                             if let Some(handler) = this.handlers.get(&message.payload_type_id()) {
                                 let notifications = this.notifications.clone();
                                 let is_background = message.is_background();
+                                let is_high_priority = is_high_priority_message(type_name);
                                 let handle_message = (handler)(this.clone(), message);
 
                                 drop(span_enter);
@@ -446,8 +851,10 @@ impl Server {
 
                                 if is_background {
                                     executor.spawn_detached(handle_message);
+                                } else if is_high_priority {
+                                    high_priority_message_handlers.push(handle_message);
                                 } else {
-                                    foreground_message_handlers.push(handle_message);
+                                    message_handlers.push(handle_message);
                                 }
                             } else {
                                 tracing::error!(%user_id, %login, %connection_id, %address, "no message handler");
@@ -460,10 +867,107 @@ impl Server {
                 }
             }
 
-            drop(foreground_message_handlers);
-            tracing::info!(%user_id, %login, %connection_id, %address, "signing out");
-            if let Err(error) = this.sign_out(connection_id).await {
-                tracing::error!(%user_id, %login, %connection_id, %address, ?error, "error signing out");
+            drop(high_priority_message_handlers);
+            drop(message_handlers);
+            // The socket went away without an intentional `leave_room`/`leave_project` message
+            // from the client (those are handled as ordinary messages above and run their own
+            // immediate cleanup). Mark this connection suspended in the `Store` instead of
+            // tearing it down: the participant keeps their room slot, project replica id, and
+            // LiveKit identity until `RECONNECT_TIMEOUT` elapses or they reconnect with a valid
+            // resume token.
+            tracing::info!(%user_id, %login, %connection_id, %address, "marking connection lost, awaiting reconnect");
+            this.store().await.suspend_connection(connection_id);
+            executor.spawn_detached({
+                let this = this.clone();
+                let executor = executor.clone();
+                async move {
+                    executor.sleep(RECONNECT_TIMEOUT).await;
+                    if this.store().await.is_suspended(connection_id) {
+                        tracing::info!(%user_id, %login, %connection_id, %address, "reconnect window elapsed, signing out");
+                        if let Err(error) = this.clone().sign_out(connection_id).await {
+                            tracing::error!(%user_id, %login, %connection_id, %address, ?error, "error signing out");
+                        }
+                    }
+                }
+            });
+
+            Ok(())
+        }.instrument(span)
+    }
+
+    /// Drives a connection from a headless project host: a remote-development agent that serves
+    /// worktrees, language servers, and buffer operations on behalf of a project, without being a
+    /// seated room participant or appearing as a contact. Unlike `handle_connection`, this skips
+    /// the interactive sign-in bookkeeping (contacts, invite info, `ShowContacts`) since there's
+    /// no human on the other end; everything else, including `forward_project_request` and
+    /// teardown of its shared projects on disconnect, goes through the same machinery an
+    /// interactive host uses.
+    pub fn handle_headless_connection<E: Executor>(
+        self: &Arc<Self>,
+        connection: Connection,
+        address: String,
+        host_user_id: UserId,
+        executor: E,
+    ) -> impl Future<Output = Result<()>> {
+        let this = self.clone();
+        let span = info_span!("handle headless connection", %host_user_id, %address);
+        async move {
+            let (connection_id, handle_io, mut incoming_rx) = this
+                .peer
+                .add_connection(connection, {
+                    let executor = executor.clone();
+                    move |duration| {
+                        let timer = executor.sleep(duration);
+                        async move {
+                            timer.await;
+                        }
+                    }
+                });
+
+            tracing::info!(%host_user_id, %connection_id, %address, "headless host connection opened");
+            this.peer.send(connection_id, proto::Hello { peer_id: connection_id.0, resume_token: None })?;
+
+            this.store()
+                .await
+                .add_headless_host_connection(connection_id, host_user_id);
+            this.connection_user_ids
+                .lock()
+                .unwrap()
+                .insert(connection_id, host_user_id);
+
+            let handle_io = handle_io.fuse();
+            futures::pin_mut!(handle_io);
+            let mut message_handlers = FuturesUnordered::new();
+            loop {
+                let next_message = incoming_rx.next().fuse();
+                futures::pin_mut!(next_message);
+                futures::select_biased! {
+                    result = handle_io => {
+                        if let Err(error) = result {
+                            tracing::error!(?error, %host_user_id, %connection_id, %address, "error handling I/O");
+                        }
+                        break;
+                    }
+                    _ = message_handlers.next() => {}
+                    message = next_message => {
+                        if let Some(message) = message {
+                            if let Some(handler) = this.handlers.get(&message.payload_type_id()) {
+                                message_handlers.push((handler)(this.clone(), message));
+                            } else {
+                                tracing::error!(%host_user_id, %connection_id, %address, "no message handler");
+                            }
+                        } else {
+                            tracing::info!(%host_user_id, %connection_id, %address, "headless host connection closed");
+                            break;
+                        }
+                    }
+                }
+            }
+
+            drop(message_handlers);
+            tracing::info!(%host_user_id, %connection_id, %address, "signing out headless host");
+            if let Err(error) = this.clone().sign_out(connection_id).await {
+                tracing::error!(%host_user_id, %connection_id, %address, ?error, "error signing out headless host");
             }
 
             Ok(())
@@ -473,10 +977,17 @@ impl Server {
     #[instrument(skip(self), err)]
     async fn sign_out(self: &mut Arc<Self>, connection_id: ConnectionId) -> Result<()> {
         self.peer.disconnect(connection_id);
+        self.app_state.message_bus.unregister(connection_id);
+        self.connection_user_ids.lock().unwrap().remove(&connection_id);
+        self.connection_protocol_versions
+            .lock()
+            .unwrap()
+            .remove(&connection_id);
 
         let mut projects_to_unshare = Vec::new();
         let mut contacts_to_update = HashSet::default();
         let mut room_left = None;
+        let mut updated_room = None;
         {
             let mut store = self.store().await;
 
@@ -510,8 +1021,20 @@ impl Server {
             }
 
             if let Some(room) = removed_connection.room {
-                self.room_updated(&room);
                 room_left = Some(self.room_left(&room, connection_id));
+                updated_room = Some(room);
+            }
+
+            for (channel_id, collaborators) in removed_connection.channel_buffers_left {
+                broadcast(connection_id, store.channel_buffer_connection_ids(channel_id)?, |conn_id| {
+                    self.peer.send(
+                        conn_id,
+                        proto::UpdateChannelBufferCollaborators {
+                            channel_id: channel_id.to_proto(),
+                            collaborators: collaborators.clone(),
+                        },
+                    )
+                });
             }
 
             contacts_to_update.insert(removed_connection.user_id);
@@ -523,6 +1046,10 @@ impl Server {
             }
         };
 
+        if let Some(room) = &updated_room {
+            self.room_updated(room).await;
+        }
+
         if let Some(room_left) = room_left {
             room_left.await.trace_err();
         }
@@ -613,8 +1140,22 @@ impl Server {
             let mut store = self.store().await;
             user_id = store.user_id_for_connection(request.sender_id)?;
             room = store.create_room(request.sender_id)?.clone();
+            // The person who opens a room is its moderator by default, with the power to
+            // downgrade, mute, or remove other participants for as long as the room exists.
+            store.set_participant_role(
+                room.id,
+                request.sender_id,
+                proto::ParticipantRole::Moderator,
+            )?;
         }
 
+        // Persist the room to the database so it (and its participants) survive a server
+        // restart, rather than only living in the in-memory `Store`. This is the first slice of
+        // moving room/project state onto a DB-backed store; `join_room`/`leave_room` and the
+        // project-sharing handlers below still read their authoritative state from `Store` and
+        // will move over incrementally.
+        self.app_state.db.create_room(room.id, user_id).await?;
+
         let live_kit_connection_info =
             if let Some(live_kit) = self.app_state.live_kit_client.as_ref() {
                 if let Some(_) = live_kit
@@ -623,7 +1164,12 @@ impl Server {
                     .trace_err()
                 {
                     if let Some(token) = live_kit
-                        .room_token(&room.live_kit_room, &request.sender_id.to_string())
+                        .room_token(
+                            &room.live_kit_room,
+                            &request.sender_id.to_string(),
+                            self.app_state.config.live_kit_token_ttl,
+                            proto::ParticipantRole::Moderator,
+                        )
                         .trace_err()
                     {
                         Some(proto::LiveKitConnectionInfo {
@@ -654,7 +1200,7 @@ impl Server {
         response: Response<proto::JoinRoom>,
     ) -> Result<()> {
         let user_id;
-        {
+        let room = {
             let mut store = self.store().await;
             user_id = store.user_id_for_connection(request.sender_id)?;
             let (room, recipient_connection_ids) =
@@ -665,10 +1211,25 @@ impl Server {
                     .trace_err();
             }
 
+            // A reconnecting moderator keeps their role (it's persisted on the room rather than
+            // reset on every join), so just read back whatever `join_room` seated them as.
+            let role = room
+                .participants
+                .iter()
+                .find(|participant| participant.peer_id == request.sender_id.0)
+                .map_or(proto::ParticipantRole::Standard, |participant| {
+                    participant.role()
+                });
+
             let live_kit_connection_info =
                 if let Some(live_kit) = self.app_state.live_kit_client.as_ref() {
                     if let Some(token) = live_kit
-                        .room_token(&room.live_kit_room, &request.sender_id.to_string())
+                        .room_token(
+                            &room.live_kit_room,
+                            &request.sender_id.to_string(),
+                            self.app_state.config.live_kit_token_ttl,
+                            role,
+                        )
                         .trace_err()
                     {
                         Some(proto::LiveKitConnectionInfo {
@@ -686,8 +1247,9 @@ impl Server {
                 room: Some(room.clone()),
                 live_kit_connection_info,
             })?;
-            self.room_updated(room);
-        }
+            room.clone()
+        };
+        self.room_updated(&room).await;
         self.update_user_contacts(user_id).await?;
         Ok(())
     }
@@ -695,6 +1257,7 @@ impl Server {
     async fn leave_room(self: Arc<Server>, message: TypedEnvelope<proto::LeaveRoom>) -> Result<()> {
         let mut contacts_to_update = HashSet::default();
         let room_left;
+        let updated_room;
         {
             let mut store = self.store().await;
             let user_id = store.user_id_for_connection(message.sender_id)?;
@@ -733,7 +1296,6 @@ impl Server {
                 }
             }
 
-            self.room_updated(&left_room.room);
             room_left = self.room_left(&left_room.room, message.sender_id);
 
             for connection_id in left_room.canceled_call_connection_ids {
@@ -742,8 +1304,11 @@ impl Server {
                     .trace_err();
                 contacts_to_update.extend(store.user_id_for_connection(connection_id).ok());
             }
+
+            updated_room = left_room.room;
         }
 
+        self.room_updated(&updated_room).await;
         room_left.await.trace_err();
         for user_id in contacts_to_update {
             self.update_user_contacts(user_id).await?;
@@ -776,7 +1341,7 @@ impl Server {
         }
 
         let room_id = request.payload.room_id;
-        let mut calls = {
+        let (room, mut calls) = {
             let mut store = self.store().await;
             let (room, recipient_connection_ids, incoming_call) = store.call(
                 room_id,
@@ -784,15 +1349,16 @@ impl Server {
                 initial_project_id,
                 request.sender_id,
             )?;
-            self.room_updated(room);
-            recipient_connection_ids
+            let calls = recipient_connection_ids
                 .into_iter()
                 .map(|recipient_connection_id| {
                     self.peer
                         .request(recipient_connection_id, incoming_call.clone())
                 })
-                .collect::<FuturesUnordered<_>>()
+                .collect::<FuturesUnordered<_>>();
+            (room.clone(), calls)
         };
+        self.room_updated(&room).await;
         self.update_user_contacts(recipient_user_id).await?;
 
         while let Some(call_response) = calls.next().await {
@@ -807,11 +1373,12 @@ impl Server {
             }
         }
 
-        {
-            let mut store = self.store().await;
-            let room = store.call_failed(room_id, recipient_user_id)?;
-            self.room_updated(&room);
-        }
+        let room = self
+            .store()
+            .await
+            .call_failed(room_id, recipient_user_id)?
+            .clone();
+        self.room_updated(&room).await;
         self.update_user_contacts(recipient_user_id).await?;
 
         Err(anyhow!("failed to ring call recipient"))?
@@ -823,7 +1390,7 @@ impl Server {
         response: Response<proto::CancelCall>,
     ) -> Result<()> {
         let recipient_user_id = UserId::from_proto(request.payload.recipient_user_id);
-        {
+        let room = {
             let mut store = self.store().await;
             let (room, recipient_connection_ids) = store.cancel_call(
                 request.payload.room_id,
@@ -835,9 +1402,10 @@ impl Server {
                     .send(recipient_id, proto::CallCanceled {})
                     .trace_err();
             }
-            self.room_updated(room);
-            response.send(proto::Ack {})?;
-        }
+            room.clone()
+        };
+        self.room_updated(&room).await;
+        response.send(proto::Ack {})?;
         self.update_user_contacts(recipient_user_id).await?;
         Ok(())
     }
@@ -847,7 +1415,7 @@ impl Server {
         message: TypedEnvelope<proto::DeclineCall>,
     ) -> Result<()> {
         let recipient_user_id;
-        {
+        let room = {
             let mut store = self.store().await;
             recipient_user_id = store.user_id_for_connection(message.sender_id)?;
             let (room, recipient_connection_ids) =
@@ -857,8 +1425,9 @@ impl Server {
                     .send(recipient_id, proto::CallCanceled {})
                     .trace_err();
             }
-            self.room_updated(room);
-        }
+            room.clone()
+        };
+        self.room_updated(&room).await;
         self.update_user_contacts(recipient_user_id).await?;
         Ok(())
     }
@@ -873,24 +1442,202 @@ impl Server {
             .payload
             .location
             .ok_or_else(|| anyhow!("invalid location"))?;
-        let mut store = self.store().await;
-        let room = store.update_participant_location(room_id, location, request.sender_id)?;
-        self.room_updated(room);
+        let room = self
+            .store()
+            .await
+            .update_participant_location(room_id, location, request.sender_id)?
+            .clone();
+        self.room_updated(&room).await;
         response.send(proto::Ack {})?;
         Ok(())
     }
 
-    fn room_updated(&self, room: &proto::Room) {
-        for participant in &room.participants {
-            self.peer
-                .send(
-                    ConnectionId(participant.peer_id),
-                    proto::RoomUpdated {
-                        room: Some(room.clone()),
-                    },
-                )
+    async fn set_participant_role(
+        self: Arc<Server>,
+        request: TypedEnvelope<proto::SetParticipantRole>,
+        response: Response<proto::SetParticipantRole>,
+    ) -> Result<()> {
+        let room_id = request.payload.room_id;
+        let role = request.payload.role();
+        let room = {
+            let mut store = self.store().await;
+            store.check_room_participant_is_moderator(room_id, request.sender_id)?;
+            let target_connection_id =
+                connection_id_for_participant(store.room(room_id)?, request.payload.user_id)?;
+            store
+                .set_participant_role(room_id, target_connection_id, role)?
+                .clone()
+        };
+        self.room_updated(&room).await;
+        response.send(proto::Ack {})?;
+        Ok(())
+    }
+
+    async fn mute_participant(
+        self: Arc<Server>,
+        request: TypedEnvelope<proto::MuteParticipant>,
+        response: Response<proto::MuteParticipant>,
+    ) -> Result<()> {
+        let room_id = request.payload.room_id;
+        let (room, target_connection_id) = {
+            let mut store = self.store().await;
+            store.check_room_participant_is_moderator(room_id, request.sender_id)?;
+            let room = store.room(room_id)?;
+            let target_connection_id =
+                connection_id_for_participant(room, request.payload.user_id)?;
+            (room.clone(), target_connection_id)
+        };
+
+        if let Some(live_kit) = self.app_state.live_kit_client.as_ref() {
+            live_kit
+                .mute_participant(room.live_kit_room.clone(), target_connection_id.to_string())
+                .await
                 .trace_err();
         }
+
+        self.room_updated(&room).await;
+        response.send(proto::Ack {})?;
+        Ok(())
+    }
+
+    async fn remove_participant_from_call(
+        self: Arc<Server>,
+        request: TypedEnvelope<proto::RemoveParticipantFromCall>,
+        response: Response<proto::RemoveParticipantFromCall>,
+    ) -> Result<()> {
+        let room_id = request.payload.room_id;
+        let (room, target_connection_id) = {
+            let mut store = self.store().await;
+            store.check_room_participant_is_moderator(room_id, request.sender_id)?;
+            let target_connection_id =
+                connection_id_for_participant(store.room(room_id)?, request.payload.user_id)?;
+            (
+                store.leave_room(room_id, target_connection_id)?.room,
+                target_connection_id,
+            )
+        };
+
+        self.peer
+            .send(target_connection_id, proto::CallCanceled {})
+            .trace_err();
+        self.room_updated(&room).await;
+        response.send(proto::Ack {})?;
+        Ok(())
+    }
+
+    async fn room_updated(&self, room: &proto::Room) {
+        for participant in &room.participants {
+            self.deliver(
+                ConnectionId(participant.peer_id),
+                proto::RoomUpdated {
+                    room: Some(room.clone()),
+                },
+            )
+            .await
+            .trace_err();
+        }
+    }
+
+    /// Sends a message to a connection, routing it to whichever node in the cluster actually
+    /// owns that connection. If `connection_id` is one of ours, this is just `peer.send`; if it
+    /// belongs to another `ServerId`, the envelope is published on the cross-server message bus
+    /// instead, and the owning node delivers it via its own local `peer`.
+    ///
+    /// Every message that goes through here is also assigned the next `outgoing_sequences` number
+    /// for the recipient's user and, unless the client has already acked that far, kept in
+    /// `replay_buffers` -- not only while `connection_id` is suspended, but from the moment it's
+    /// sent. That's what makes resume lossless: if the socket dies between the write and the
+    /// client's TCP ack, or dies in the gap before `suspend_connection` marks it, the message is
+    /// still in the buffer for `handle_connection` to replay, in order, the next time this user's
+    /// client presents a resume token with its last-received sequence number. A plain
+    /// "only buffer while suspended" scheme would silently drop exactly those racy in-flight
+    /// messages.
+    ///
+    /// If `connection_id` is currently suspended, the message is buffered only -- not handed to
+    /// `peer.send`/the message bus, since there is no live socket to receive it.
+    ///
+    /// Other call sites in this file still call `self.peer.send`/`broadcast` directly and assume
+    /// every recipient is local; they're being migrated over to this helper incrementally as part
+    /// of the move to horizontal scaling.
+    async fn deliver<T>(&self, connection_id: ConnectionId, message: T) -> Result<()>
+    where
+        T: EnvelopedMessage + Send + Clone + 'static,
+    {
+        METRIC_MESSAGES_SENT
+            .with_label_values(&[std::any::type_name::<T>()])
+            .inc();
+
+        let suspended = self.store().await.is_suspended(connection_id);
+        let user_id = self
+            .connection_user_ids
+            .lock()
+            .unwrap()
+            .get(&connection_id)
+            .copied();
+
+        if let Some(user_id) = user_id {
+            let sequence = {
+                let mut outgoing_sequences = self.outgoing_sequences.lock().unwrap();
+                let sequence = outgoing_sequences.entry(user_id).or_insert(0);
+                *sequence += 1;
+                *sequence
+            };
+            let acked = self
+                .acked_sequences
+                .lock()
+                .unwrap()
+                .get(&user_id)
+                .copied()
+                .unwrap_or(0);
+            if sequence > acked {
+                let replay_message = message.clone();
+                let mut replay_buffers = self.replay_buffers.lock().unwrap();
+                let buffer = replay_buffers.entry(user_id).or_default();
+                if buffer.len() == REPLAY_BUFFER_CAPACITY {
+                    buffer.pop_front();
+                }
+                buffer.push_back((
+                    sequence,
+                    Box::new(move |peer, connection_id| {
+                        peer.send(connection_id, replay_message).trace_err();
+                    }),
+                ));
+            }
+        }
+
+        if suspended {
+            return Ok(());
+        }
+
+        match self.app_state.message_bus.owner_of(connection_id) {
+            Some(owner) if owner != self.id => self
+                .app_state
+                .message_bus
+                .publish(owner, connection_id, message),
+            _ => self.peer.send(connection_id, message),
+        }
+    }
+
+    /// Handles a client's periodic report of the highest `outgoing_sequences` number it has
+    /// received, so `deliver` can stop growing `replay_buffers` for messages this user's client
+    /// has already confirmed and so a *future* reconnect only has to replay the genuinely unacked
+    /// tail instead of everything sent this session.
+    async fn acknowledge_messages(
+        self: Arc<Server>,
+        message: Message<proto::AcknowledgeMessages>,
+    ) -> Result<()> {
+        let user_id = message.sender_user_id;
+        let sequence = message.payload.sequence;
+        self.acked_sequences
+            .lock()
+            .unwrap()
+            .entry(user_id)
+            .and_modify(|acked| *acked = (*acked).max(sequence))
+            .or_insert(sequence);
+        if let Some(buffer) = self.replay_buffers.lock().unwrap().get_mut(&user_id) {
+            buffer.retain(|(buffered_sequence, _)| *buffered_sequence > sequence);
+        }
+        Ok(())
     }
 
     fn room_left(
@@ -926,17 +1673,42 @@ impl Server {
             .await
             .user_id_for_connection(request.sender_id)?;
         let project_id = self.app_state.db.register_project(user_id).await?;
-        let mut store = self.store().await;
-        let room = store.share_project(
-            request.payload.room_id,
-            project_id,
-            request.payload.worktrees,
-            request.sender_id,
-        )?;
+
+        // A headless host (a build server, CI runner, or dev container) shares a project over
+        // its persistent background connection rather than a room seat, so there's no room to
+        // associate the project with and no room-level broadcast to send afterwards. Guests
+        // reach it purely through `join_project`, addressed by the host's `ConnectionId`.
+        let is_headless = self.store().await.is_headless_connection(request.sender_id);
+        let room = if is_headless {
+            self.store().await.share_headless_project(
+                project_id,
+                request.payload.worktrees,
+                request.sender_id,
+            )?;
+            None
+        } else {
+            self.app_state
+                .db
+                .associate_project_with_room(project_id, request.payload.room_id)
+                .await?;
+            Some(
+                self.store()
+                    .await
+                    .share_project(
+                        request.payload.room_id,
+                        project_id,
+                        request.payload.worktrees,
+                        request.sender_id,
+                    )?
+                    .clone(),
+            )
+        };
         response.send(proto::ShareProjectResponse {
             project_id: project_id.to_proto(),
         })?;
-        self.room_updated(room);
+        if let Some(room) = &room {
+            self.room_updated(room).await;
+        }
 
         Ok(())
     }
@@ -946,14 +1718,17 @@ impl Server {
         message: TypedEnvelope<proto::UnshareProject>,
     ) -> Result<()> {
         let project_id = ProjectId::from_proto(message.payload.project_id);
-        let mut store = self.store().await;
-        let (room, project) = store.unshare_project(project_id, message.sender_id)?;
-        broadcast(
-            message.sender_id,
-            project.guest_connection_ids(),
-            |conn_id| self.peer.send(conn_id, message.payload.clone()),
-        );
-        self.room_updated(room);
+        let room = {
+            let mut store = self.store().await;
+            let (room, project) = store.unshare_project(project_id, message.sender_id)?;
+            broadcast(
+                message.sender_id,
+                project.guest_connection_ids(),
+                |conn_id| self.peer.send(conn_id, message.payload.clone()),
+            );
+            room.clone()
+        };
+        self.room_updated(&room).await;
 
         Ok(())
     }
@@ -995,6 +1770,9 @@ impl Server {
     ) -> Result<()> {
         let project_id = ProjectId::from_proto(request.payload.project_id);
 
+        // `project.host_connection_id` may point at a headless host that never took a room
+        // seat; nothing below reads the room on the host's behalf, so a guest can join a
+        // headless project exactly as they would any other.
         let host_user_id;
         let guest_user_id;
         let host_connection_id;
@@ -1153,7 +1931,7 @@ impl Server {
         request: TypedEnvelope<proto::UpdateProject>,
     ) -> Result<()> {
         let project_id = ProjectId::from_proto(request.payload.project_id);
-        {
+        let room = {
             let mut state = self.store().await;
             let guest_connection_ids = state
                 .read_project(project_id, request.sender_id)?
@@ -1164,8 +1942,9 @@ impl Server {
                 self.peer
                     .forward_send(request.sender_id, connection_id, request.payload.clone())
             });
-            self.room_updated(room);
+            room.clone()
         };
+        self.room_updated(&room).await;
 
         Ok(())
     }
@@ -1174,6 +1953,9 @@ impl Server {
         self: Arc<Server>,
         request: TypedEnvelope<proto::RegisterProjectActivity>,
     ) -> Result<()> {
+        // Headless projects are never tied to a room, so they're never candidates for the
+        // idle-room reaping that runs off of room participant activity; recording activity here
+        // is only ever used to keep per-user/per-project billing stats accurate for them.
         self.store().await.register_project_activity(
             ProjectId::from_proto(request.payload.project_id),
             request.sender_id,
@@ -1213,19 +1995,59 @@ impl Server {
     ) -> Result<()> {
         let project_id = ProjectId::from_proto(request.payload.project_id);
         let worktree_id = request.payload.worktree_id;
-        let extensions = request
-            .payload
+        let user_id = self
+            .store()
+            .await
+            .user_id_for_connection(request.sender_id)?;
+        let extensions = request
+            .payload
             .extensions
-            .into_iter()
-            .zip(request.payload.counts)
+            .iter()
+            .zip(&request.payload.counts)
+            .map(|(extension, count)| (extension.clone(), *count))
             .collect();
+
+        let mut language_counts = HashMap::<&'static str, u32>::default();
+        for (extension, count) in request.payload.extensions.iter().zip(&request.payload.counts) {
+            *language_counts.entry(language_for_extension(extension)).or_default() += count;
+        }
+
+        // The raw per-extension counts and the language aggregate derived from them are written
+        // by a single transactional db call, so a project's language stats can never observe one
+        // updated without the other even if the write fails partway through.
         self.app_state
             .db
-            .update_worktree_extensions(project_id, worktree_id, extensions)
+            .update_worktree_extensions_and_language_stats(
+                project_id,
+                worktree_id,
+                extensions,
+                user_id,
+                language_counts,
+            )
             .await?;
         Ok(())
     }
 
+    async fn get_project_language_stats(
+        self: Arc<Server>,
+        request: TypedEnvelope<proto::GetProjectLanguageStats>,
+        response: Response<proto::GetProjectLanguageStats>,
+    ) -> Result<()> {
+        let project_id = ProjectId::from_proto(request.payload.project_id);
+        self.store().await.project(project_id)?;
+        let language_stats = self.app_state.db.get_project_language_stats(project_id).await?;
+        response.send(proto::GetProjectLanguageStatsResponse {
+            language_percentages: language_stats
+                .into_iter()
+                .map(|(language, percentage)| proto::LanguageStat {
+                    language,
+                    percentage,
+                })
+                .collect(),
+        })?;
+        Ok(())
+    }
+
     async fn update_diagnostic_summary(
         self: Arc<Server>,
         request: TypedEnvelope<proto::UpdateDiagnosticSummary>,
@@ -1299,7 +2121,6 @@ impl Server {
             .read_project(project_id, request.sender_id)?
             .host_connection_id;
         let payload = self
-            .peer
             .forward_request(request.sender_id, host_connection_id, request.payload)
             .await?;
 
@@ -1324,7 +2145,6 @@ impl Server {
             .read_project(project_id, request.sender_id)?
             .host_connection_id;
         let response_payload = self
-            .peer
             .forward_request(request.sender_id, host, request.payload.clone())
             .await?;
 
@@ -1440,7 +2260,6 @@ impl Server {
         }
 
         let mut response_payload = self
-            .peer
             .forward_request(request.sender_id, leader_id, request.payload)
             .await?;
         response_payload
@@ -1495,14 +2314,14 @@ impl Server {
 
     async fn get_channels(
         self: Arc<Server>,
-        request: TypedEnvelope<proto::GetChannels>,
+        request: Message<proto::GetChannels>,
         response: Response<proto::GetChannels>,
     ) -> Result<()> {
-        let user_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
-        let channels = self.app_state.db.get_accessible_channels(user_id).await?;
+        let channels = self
+            .app_state
+            .db
+            .get_accessible_channels(request.sender_user_id)
+            .await?;
         response.send(proto::GetChannelsResponse {
             channels: channels
                 .into_iter()
@@ -1544,13 +2363,10 @@ impl Server {
 
     async fn fuzzy_search_users(
         self: Arc<Server>,
-        request: TypedEnvelope<proto::FuzzySearchUsers>,
+        request: Message<proto::FuzzySearchUsers>,
         response: Response<proto::FuzzySearchUsers>,
     ) -> Result<()> {
-        let user_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+        let user_id = request.sender_user_id;
         let query = request.payload.query;
         let db = &self.app_state.db;
         let users = match query.len() {
@@ -1577,13 +2393,10 @@ impl Server {
 
     async fn request_contact(
         self: Arc<Server>,
-        request: TypedEnvelope<proto::RequestContact>,
+        request: Message<proto::RequestContact>,
         response: Response<proto::RequestContact>,
     ) -> Result<()> {
-        let requester_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+        let requester_id = request.sender_user_id;
         let responder_id = UserId::from_proto(request.payload.responder_id);
         if requester_id == responder_id {
             return Err(anyhow!("cannot add yourself as a contact"))?;
@@ -1598,7 +2411,7 @@ impl Server {
         let mut update = proto::UpdateContacts::default();
         update.outgoing_requests.push(responder_id.to_proto());
         for connection_id in self.store().await.connection_ids_for_user(requester_id) {
-            self.peer.send(connection_id, update.clone())?;
+            self.deliver(connection_id, update.clone()).await?;
         }
 
         // Update incoming contact requests of responder
@@ -1610,7 +2423,7 @@ impl Server {
                 should_notify: true,
             });
         for connection_id in self.store().await.connection_ids_for_user(responder_id) {
-            self.peer.send(connection_id, update.clone())?;
+            self.deliver(connection_id, update.clone()).await?;
         }
 
         response.send(proto::Ack {})?;
@@ -1619,13 +2432,10 @@ impl Server {
 
     async fn respond_to_contact_request(
         self: Arc<Server>,
-        request: TypedEnvelope<proto::RespondToContactRequest>,
+        request: Message<proto::RespondToContactRequest>,
         response: Response<proto::RespondToContactRequest>,
     ) -> Result<()> {
-        let responder_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+        let responder_id = request.sender_user_id;
         let requester_id = UserId::from_proto(request.payload.requester_id);
         if request.payload.response == proto::ContactRequestResponse::Dismiss as i32 {
             self.app_state
@@ -1639,33 +2449,37 @@ impl Server {
                 .respond_to_contact_request(responder_id, requester_id, accept)
                 .await?;
 
-            let store = self.store().await;
             // Update responder with new contact
-            let mut update = proto::UpdateContacts::default();
-            if accept {
-                update
-                    .contacts
-                    .push(store.contact_for_user(requester_id, false));
-            }
-            update
+            let mut responder_update = proto::UpdateContacts::default();
+            // Update requester with new contact
+            let mut requester_update = proto::UpdateContacts::default();
+            let (responder_connection_ids, requester_connection_ids) = {
+                let store = self.store().await;
+                if accept {
+                    responder_update
+                        .contacts
+                        .push(store.contact_for_user(requester_id, false));
+                    requester_update
+                        .contacts
+                        .push(store.contact_for_user(responder_id, true));
+                }
+                (
+                    store.connection_ids_for_user(responder_id),
+                    store.connection_ids_for_user(requester_id),
+                )
+            };
+            responder_update
                 .remove_incoming_requests
                 .push(requester_id.to_proto());
-            for connection_id in store.connection_ids_for_user(responder_id) {
-                self.peer.send(connection_id, update.clone())?;
-            }
-
-            // Update requester with new contact
-            let mut update = proto::UpdateContacts::default();
-            if accept {
-                update
-                    .contacts
-                    .push(store.contact_for_user(responder_id, true));
-            }
-            update
+            requester_update
                 .remove_outgoing_requests
                 .push(responder_id.to_proto());
-            for connection_id in store.connection_ids_for_user(requester_id) {
-                self.peer.send(connection_id, update.clone())?;
+
+            for connection_id in responder_connection_ids {
+                self.deliver(connection_id, responder_update.clone()).await?;
+            }
+            for connection_id in requester_connection_ids {
+                self.deliver(connection_id, requester_update.clone()).await?;
             }
         }
 
@@ -1675,13 +2489,10 @@ impl Server {
 
     async fn remove_contact(
         self: Arc<Server>,
-        request: TypedEnvelope<proto::RemoveContact>,
+        request: Message<proto::RemoveContact>,
         response: Response<proto::RemoveContact>,
     ) -> Result<()> {
-        let requester_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+        let requester_id = request.sender_user_id;
         let responder_id = UserId::from_proto(request.payload.user_id);
         self.app_state
             .db
@@ -1694,7 +2505,7 @@ impl Server {
             .remove_outgoing_requests
             .push(responder_id.to_proto());
         for connection_id in self.store().await.connection_ids_for_user(requester_id) {
-            self.peer.send(connection_id, update.clone())?;
+            self.deliver(connection_id, update.clone()).await?;
         }
 
         // Update incoming contact requests of responder
@@ -1703,7 +2514,7 @@ impl Server {
             .remove_incoming_requests
             .push(requester_id.to_proto());
         for connection_id in self.store().await.connection_ids_for_user(responder_id) {
-            self.peer.send(connection_id, update.clone())?;
+            self.deliver(connection_id, update.clone()).await?;
         }
 
         response.send(proto::Ack {})?;
@@ -1753,19 +2564,12 @@ impl Server {
         Ok(())
     }
 
-    async fn leave_channel(
-        self: Arc<Self>,
-        request: TypedEnvelope<proto::LeaveChannel>,
-    ) -> Result<()> {
-        let user_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+    async fn leave_channel(self: Arc<Self>, request: Message<proto::LeaveChannel>) -> Result<()> {
         let channel_id = ChannelId::from_proto(request.payload.channel_id);
         if !self
             .app_state
             .db
-            .can_user_access_channel(user_id, channel_id)
+            .can_user_access_channel(request.sender_user_id, channel_id)
             .await?
         {
             Err(anyhow!("access denied"))?;
@@ -1773,24 +2577,19 @@ impl Server {
 
         self.store()
             .await
-            .leave_channel(request.sender_id, channel_id);
+            .leave_channel(request.sender_connection_id, channel_id);
 
         Ok(())
     }
 
     async fn send_channel_message(
         self: Arc<Self>,
-        request: TypedEnvelope<proto::SendChannelMessage>,
+        request: Message<proto::SendChannelMessage>,
         response: Response<proto::SendChannelMessage>,
     ) -> Result<()> {
         let channel_id = ChannelId::from_proto(request.payload.channel_id);
-        let user_id;
-        let connection_ids;
-        {
-            let state = self.store().await;
-            user_id = state.user_id_for_connection(request.sender_id)?;
-            connection_ids = state.channel_connection_ids(channel_id)?;
-        }
+        let user_id = request.sender_user_id;
+        let connection_ids = self.store().await.channel_connection_ids(channel_id)?;
 
         // Validate the message body.
         let body = request.payload.body.trim().to_string();
@@ -1820,7 +2619,10 @@ impl Server {
             timestamp: timestamp.unix_timestamp() as u64,
             nonce: Some(nonce),
         };
-        broadcast(request.sender_id, connection_ids, |conn_id| {
+        broadcast(request.sender_connection_id, connection_ids, |conn_id| {
+            // `broadcast`'s callback is synchronous, so this fan-out can't go through the
+            // (now async, suspension-aware) `deliver` helper without blocking; it's migrated
+            // to `deliver` once `broadcast` itself grows an async variant.
             self.peer.send(
                 conn_id,
                 proto::ChannelMessageSent {
@@ -1837,13 +2639,10 @@ impl Server {
 
     async fn get_channel_messages(
         self: Arc<Self>,
-        request: TypedEnvelope<proto::GetChannelMessages>,
+        request: Message<proto::GetChannelMessages>,
         response: Response<proto::GetChannelMessages>,
     ) -> Result<()> {
-        let user_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+        let user_id = request.sender_user_id;
         let channel_id = ChannelId::from_proto(request.payload.channel_id);
         if !self
             .app_state
@@ -1879,6 +2678,106 @@ impl Server {
         Ok(())
     }
 
+    /// Joins the shared, concurrently-editable buffer for a channel. The caller gets back the
+    /// operation log collected so far (or a compacted snapshot plus tail) so it can reconstruct
+    /// the document, along with the set of collaborators currently editing it.
+    async fn join_channel_buffer(
+        self: Arc<Self>,
+        request: TypedEnvelope<proto::JoinChannelBuffer>,
+        response: Response<proto::JoinChannelBuffer>,
+    ) -> Result<()> {
+        let user_id = self
+            .store()
+            .await
+            .user_id_for_connection(request.sender_id)?;
+        let channel_id = ChannelId::from_proto(request.payload.channel_id);
+        if !self
+            .app_state
+            .db
+            .can_user_access_channel(user_id, channel_id)
+            .await?
+        {
+            Err(anyhow!("access denied"))?;
+        }
+
+        let (base_text, operations) = self.app_state.db.get_channel_buffer_ops(channel_id).await?;
+
+        let mut store = self.store().await;
+        let (replica_id, collaborators) =
+            store.join_channel_buffer(request.sender_id, channel_id, user_id)?;
+        drop(store);
+
+        let connection_ids = self.store().await.channel_buffer_connection_ids(channel_id)?;
+        broadcast(request.sender_id, connection_ids, |conn_id| {
+            self.peer.send(
+                conn_id,
+                proto::UpdateChannelBufferCollaborators {
+                    channel_id: channel_id.to_proto(),
+                    collaborators: collaborators.clone(),
+                },
+            )
+        });
+
+        response.send(proto::JoinChannelBufferResponse {
+            base_text,
+            operations,
+            replica_id: replica_id as u32,
+            collaborators,
+        })?;
+        Ok(())
+    }
+
+    async fn leave_channel_buffer(
+        self: Arc<Self>,
+        request: TypedEnvelope<proto::LeaveChannelBuffer>,
+    ) -> Result<()> {
+        let channel_id = ChannelId::from_proto(request.payload.channel_id);
+        let collaborators = self
+            .store()
+            .await
+            .leave_channel_buffer(request.sender_id, channel_id)?;
+        let connection_ids = self.store().await.channel_buffer_connection_ids(channel_id)?;
+        broadcast(request.sender_id, connection_ids, |conn_id| {
+            self.peer.send(
+                conn_id,
+                proto::UpdateChannelBufferCollaborators {
+                    channel_id: channel_id.to_proto(),
+                    collaborators: collaborators.clone(),
+                },
+            )
+        });
+        Ok(())
+    }
+
+    /// Accepts a batch of CRDT operations for a channel buffer. Each operation carries a unique
+    /// `(replica_id, lamport_timestamp)` and the ids it logically follows, so concurrent edits
+    /// from different collaborators converge without any server-side locking; the server's only
+    /// job is to persist the operations to the append-only log and fan them out in the order it
+    /// received them.
+    async fn update_channel_buffer(
+        self: Arc<Self>,
+        request: TypedEnvelope<proto::UpdateChannelBuffer>,
+        response: Response<proto::UpdateChannelBuffer>,
+    ) -> Result<()> {
+        let channel_id = ChannelId::from_proto(request.payload.channel_id);
+        let connection_ids = self
+            .store()
+            .await
+            .channel_buffer_connection_ids(channel_id)?;
+
+        self.app_state
+            .db
+            .append_channel_buffer_ops(channel_id, &request.payload.operations)
+            .await?;
+
+        broadcast(request.sender_id, connection_ids, |connection_id| {
+            self.peer
+                .forward_send(request.sender_id, connection_id, request.payload.clone())
+        });
+        response.send(proto::Ack {})?;
+        Ok(())
+    }
+
     async fn update_diff_base(
         self: Arc<Server>,
         request: TypedEnvelope<proto::UpdateDiffBase>,
@@ -1896,13 +2795,10 @@ impl Server {
 
     async fn get_private_user_info(
         self: Arc<Self>,
-        request: TypedEnvelope<proto::GetPrivateUserInfo>,
+        request: Message<proto::GetPrivateUserInfo>,
         response: Response<proto::GetPrivateUserInfo>,
     ) -> Result<()> {
-        let user_id = self
-            .store()
-            .await
-            .user_id_for_connection(request.sender_id)?;
+        let user_id = request.sender_user_id;
         let metrics_id = self.app_state.db.get_user_metrics_id(user_id).await?;
         let user = self
             .app_state
@@ -1917,6 +2813,25 @@ impl Server {
         Ok(())
     }
 
+    /// Forwards a request to another peer, recording its round-trip latency under the request's
+    /// type name. This is the only thing `forward_project_request`, `save_buffer`, and `follow`
+    /// await that isn't a local store lookup, so it's the one place worth a histogram rather
+    /// than just a log line: a host that's slow to answer shows up here long before a user files
+    /// a complaint about laggy completions.
+    async fn forward_request<T: RequestMessage>(
+        &self,
+        sender_id: ConnectionId,
+        receiver_id: ConnectionId,
+        payload: T,
+    ) -> Result<T::Response> {
+        let _timer = METRIC_FORWARD_REQUEST_DURATION
+            .with_label_values(&[std::any::type_name::<T>()])
+            .start_timer();
+        self.peer
+            .forward_request(sender_id, receiver_id, payload)
+            .await
+    }
+
     pub(crate) async fn store(&self) -> StoreGuard<'_> {
         #[cfg(test)]
         tokio::task::yield_now().await;
@@ -1970,6 +2885,26 @@ impl Executor for RealExecutor {
     }
 }
 
+/// Control and call-signaling messages that should be processed ahead of bulk project-sync
+/// traffic (`update_worktree`, `update_buffer`, ...) so a large project sync can't starve
+/// interactive RPCs like pings or incoming calls.
+fn is_high_priority_message(type_name: &'static str) -> bool {
+    matches!(
+        type_name,
+        "Ping"
+            | "Call"
+            | "CancelCall"
+            | "DeclineCall"
+            | "UpdateParticipantLocation"
+            | "LeaveRoom"
+    )
+}
+
+/// Fans a message out to every receiver except the sender. Enqueuing onto a connection's
+/// outgoing queue can't fail except when the connection is already gone (the queue itself is
+/// unbounded, so a lagging peer never blocks this loop); `f`'s `Result` only ever surfaces that
+/// kind of real disconnect, which we log and otherwise ignore rather than letting one vanished
+/// receiver abort delivery to the rest.
 fn broadcast<F>(
     sender_id: ConnectionId,
     receiver_ids: impl IntoIterator<Item = ConnectionId>,
@@ -1979,6 +2914,7 @@ fn broadcast<F>(
 {
     for receiver_id in receiver_ids {
         if receiver_id != sender_id {
+            METRIC_BROADCAST_FAN_OUT.inc();
             f(receiver_id).trace_err();
         }
     }
@@ -1986,10 +2922,123 @@ fn broadcast<F>(
 
 lazy_static! {
     static ref ZED_PROTOCOL_VERSION: HeaderName = HeaderName::from_static("x-zed-protocol-version");
+    static ref ZED_RESUME_TOKEN: HeaderName = HeaderName::from_static("x-zed-resume-token");
+    static ref ZED_LAST_SEQUENCE_ACKED: HeaderName =
+        HeaderName::from_static("x-zed-last-sequence-acked");
 }
 
 pub struct ProtocolVersion(u32);
 
+/// Per-connection limits applied to every `/rpc`/`/rpc_headless` WebSocket upgrade: how large a
+/// single message/frame we'll buffer for a peer, and how much unsent data we'll hold in the
+/// outgoing write buffer before applying backpressure. Tunable per deployment via
+/// `Server::set_websocket_config`; the defaults are generous enough for normal collab traffic
+/// (the largest messages are buffer contents and diffs) while still bounding a single connection's
+/// worst-case memory footprint.
+///
+/// `max_message_size`/`max_frame_size` are enforced by the underlying WebSocket implementation,
+/// which closes the connection with a `1009` (message too big) close frame when a peer exceeds
+/// them, rather than erroring out of the connection without telling the peer why.
+#[derive(Clone, Copy, Debug)]
+pub struct WebSocketConfig {
+    pub max_message_size: usize,
+    pub max_frame_size: usize,
+    pub max_write_buffer_size: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 << 20,
+            max_frame_size: 16 << 20,
+            max_write_buffer_size: 64 << 20,
+        }
+    }
+}
+
+/// The oldest client protocol version this server still accepts. Clients older than this get the
+/// hard `426 Upgrade Required` rejection; anything in `[MIN_SUPPORTED_PROTOCOL_VERSION,
+/// rpc::PROTOCOL_VERSION]` is accepted and told which version was actually negotiated, so a
+/// rolling deploy doesn't force every client to upgrade in lockstep with the server.
+const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = rpc::PROTOCOL_VERSION - 1;
+
+/// Negotiates a protocol version for a connecting client: the highest version both ends support,
+/// which today is just the client's own version as long as it falls within
+/// `[MIN_SUPPORTED_PROTOCOL_VERSION, rpc::PROTOCOL_VERSION]`. Returns `None` if the client is too
+/// old (or, in principle, too new) for this server to talk to at all.
+fn negotiate_protocol_version(client_version: u32) -> Option<u32> {
+    if (MIN_SUPPORTED_PROTOCOL_VERSION..=rpc::PROTOCOL_VERSION).contains(&client_version) {
+        Some(client_version)
+    } else {
+        None
+    }
+}
+
+/// A reconnect token handed back to the client in `proto::Hello`, presented on a later
+/// connection attempt to resume a still-suspended connection instead of signing in fresh.
+pub struct ZedResumeToken(String);
+
+impl Header for ZedResumeToken {
+    fn name() -> &'static HeaderName {
+        &ZED_RESUME_TOKEN
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, axum::headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i axum::http::HeaderValue>,
+    {
+        let token = values
+            .next()
+            .ok_or_else(axum::headers::Error::invalid)?
+            .to_str()
+            .map_err(|_| axum::headers::Error::invalid())?
+            .to_string();
+        Ok(Self(token))
+    }
+
+    fn encode<E: Extend<axum::http::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(value) = self.0.parse() {
+            values.extend([value]);
+        }
+    }
+}
+
+/// The highest `outgoing_sequences` number the client says it received from its previous,
+/// now-dead connection, presented alongside `ZedResumeToken` on reconnect. This is the
+/// "last-received exchange" half of lossless resume: paired with `Server::acked_sequences`, it's
+/// what lets `handle_connection` replay exactly the unacked tail of `replay_buffers` instead of
+/// either replaying everything (duplicating messages the client already has) or nothing (losing
+/// messages sent during the outage).
+pub struct ZedLastSequence(u64);
+
+impl Header for ZedLastSequence {
+    fn name() -> &'static HeaderName {
+        &ZED_LAST_SEQUENCE_ACKED
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, axum::headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i axum::http::HeaderValue>,
+    {
+        let sequence = values
+            .next()
+            .ok_or_else(axum::headers::Error::invalid)?
+            .to_str()
+            .map_err(|_| axum::headers::Error::invalid())?
+            .parse()
+            .map_err(|_| axum::headers::Error::invalid())?;
+        Ok(Self(sequence))
+    }
+
+    fn encode<E: Extend<axum::http::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(value) = self.0.to_string().parse() {
+            values.extend([value]);
+        }
+    }
+}
+
 impl Header for ProtocolVersion {
     fn name() -> &'static HeaderName {
         &ZED_PROTOCOL_VERSION
@@ -2017,45 +3066,468 @@ impl Header for ProtocolVersion {
 
 pub fn routes(server: Arc<Server>) -> Router<Body> {
     Router::new()
-        .route("/rpc", get(handle_websocket_request))
+        // `any` (rather than `get`) so this also matches the extended CONNECT requests HTTP/2
+        // clients send for WebSockets under RFC 8441; `WebSocketUpgrade` already inspects the
+        // request's version/method to decide between a classic 101 handshake and an h2 200-response
+        // stream, so `handle_websocket_request` itself needs no version-specific branching. Extended
+        // CONNECT still has to be turned on where the server's `hyper::server::conn::Http` is built
+        // (`.http2().enable_connect_protocol()`), which lives outside this crate.
+        .route("/rpc", any(handle_websocket_request))
         .layer(
             ServiceBuilder::new()
                 .layer(Extension(server.app_state.clone()))
                 .layer(middleware::from_fn(auth::validate_header)),
         )
+        .route("/rpc_headless", get(handle_headless_websocket_request))
+        .layer(
+            ServiceBuilder::new()
+                .layer(Extension(server.app_state.clone()))
+                .layer(middleware::from_fn(auth::validate_headless_header)),
+        )
         .route("/metrics", get(handle_metrics))
         .layer(Extension(server))
 }
 
+/// The extension token we advertise in `Sec-WebSocket-Extensions`. This is deliberately *not*
+/// the standard `permessage-deflate` token: axum's `WebSocket` only hands us decoded `Message`s,
+/// not raw frames, so we have no way to set the RSV1 bit or do frame-level `00 00 FF FF`
+/// stripping/appending the way RFC 7692 requires. `PerMessageDeflate` below instead compresses
+/// `Binary` message payloads in place and marks them with a private 1-byte prefix, which is only
+/// safe to do with a peer that also speaks this exact, non-standard scheme -- advertising the
+/// real `permessage-deflate` token here would make a standards-compliant client believe we
+/// support the RFC, and it would never inflate our frames (or would double-process them).
+const ZED_DEFLATE_EXTENSION: &str = "x-zed-deflate";
+
+/// Below this payload size, compressing a frame costs more in CPU and framing overhead than it
+/// saves on the wire, so `PerMessageDeflate` passes small frames through unchanged.
+const COMPRESSION_THRESHOLD_BYTES: usize = 860;
+
+/// An `x-zed-deflate` offer, as parsed from a client's `Sec-WebSocket-Extensions` header. The
+/// parameter names are borrowed from `permessage-deflate` (RFC 7692) for familiarity, but this
+/// is our own private extension, not the standardized one -- see `ZED_DEFLATE_EXTENSION`. We
+/// always go along with whatever `*_no_context_takeover`/`*_max_window_bits` the client asks
+/// for, since none of them affect correctness here, only the compression ratio/memory tradeoff.
+#[derive(Clone, Copy, Debug, Default)]
+struct PermessageDeflateParams {
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+    client_max_window_bits: Option<u8>,
+    server_max_window_bits: Option<u8>,
+}
+
+/// Looks for an `x-zed-deflate` offer in the client's `Sec-WebSocket-Extensions` header and,
+/// if present, returns the parameters we'll accept.
+fn negotiate_permessage_deflate(headers: &HeaderMap) -> Option<PermessageDeflateParams> {
+    let offer = headers
+        .get(axum::http::header::SEC_WEBSOCKET_EXTENSIONS)?
+        .to_str()
+        .ok()?;
+    for extension in offer.split(',') {
+        let mut parts = extension.split(';').map(str::trim);
+        if parts.next()? != ZED_DEFLATE_EXTENSION {
+            continue;
+        }
+
+        let mut params = PermessageDeflateParams::default();
+        for param in parts {
+            let mut key_value = param.splitn(2, '=');
+            match key_value.next().unwrap_or("").trim() {
+                "client_no_context_takeover" => params.client_no_context_takeover = true,
+                "server_no_context_takeover" => params.server_no_context_takeover = true,
+                "client_max_window_bits" => {
+                    params.client_max_window_bits = key_value
+                        .next()
+                        .and_then(|bits| bits.trim().trim_matches('"').parse().ok());
+                }
+                "server_max_window_bits" => {
+                    params.server_max_window_bits = key_value
+                        .next()
+                        .and_then(|bits| bits.trim().trim_matches('"').parse().ok());
+                }
+                _ => {}
+            }
+        }
+        return Some(params);
+    }
+    None
+}
+
+/// Encodes the accepted `x-zed-deflate` offer for the `Sec-WebSocket-Extensions` response
+/// header, echoing back whichever parameters the client asked for.
+fn encode_permessage_deflate_response(params: &PermessageDeflateParams) -> HeaderValue {
+    let mut response = ZED_DEFLATE_EXTENSION.to_string();
+    if params.client_no_context_takeover {
+        response.push_str("; client_no_context_takeover");
+    }
+    if params.server_no_context_takeover {
+        response.push_str("; server_no_context_takeover");
+    }
+    if let Some(bits) = params.client_max_window_bits {
+        response.push_str(&format!("; client_max_window_bits={bits}"));
+    }
+    if let Some(bits) = params.server_max_window_bits {
+        response.push_str(&format!("; server_max_window_bits={bits}"));
+    }
+    HeaderValue::from_str(&response)
+        .unwrap_or_else(|_| HeaderValue::from_static(ZED_DEFLATE_EXTENSION))
+}
+
+/// Per-connection DEFLATE (de)compressor pair for a negotiated `x-zed-deflate` extension.
+///
+/// This is intentionally not the standard `permessage-deflate` (RFC 7692): axum's `WebSocket`
+/// only hands us decoded `Message`s, not raw frames, so there's no RSV1 bit to flip here the way
+/// a frame-level implementation would. Instead we compress/decompress the payload of `Binary`
+/// messages in place and mark them with a private 1-byte prefix, which is transparent to
+/// `Connection` on both ends since both sides of a `/rpc` socket always run this same code --
+/// but it is only safe between two peers that both speak this private scheme, which is why we
+/// advertise `x-zed-deflate` rather than claiming RFC 7692 compliance.
+struct PerMessageDeflate {
+    params: PermessageDeflateParams,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl PerMessageDeflate {
+    fn new(params: PermessageDeflateParams) -> Self {
+        Self {
+            params,
+            compress: Compress::new(Compression::fast(), false),
+            decompress: Decompress::new(false),
+        }
+    }
+
+    /// Compresses `payload`, stripping the trailing empty, non-final block (`00 00 FF FF`) that
+    /// RFC 7692 requires the sender to omit from the wire format.
+    fn compress(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(payload.len());
+        let _ = self
+            .compress
+            .compress_vec(payload, &mut output, FlushCompress::Sync);
+        output.truncate(output.len().saturating_sub(4));
+        if self.params.server_no_context_takeover {
+            self.compress.reset();
+        }
+        output
+    }
+
+    /// Reverses `compress`: re-appends the empty block the sender stripped, then inflates.
+    fn decompress(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut input = payload.to_vec();
+        input.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+        let mut output = Vec::with_capacity(payload.len() * 3);
+        self.decompress
+            .decompress_vec(&input, &mut output, FlushDecompress::Sync)
+            .map_err(|error| anyhow!("permessage-deflate inflate failed: {error}"))?;
+        if self.params.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+
+    /// Prefixes a one-byte marker (since we can't flip the real RSV1 bit at this abstraction
+    /// layer) so the peer's `decode_frame` knows whether this frame's payload went through
+    /// `compress`. Frames below `COMPRESSION_THRESHOLD_BYTES` are sent through unchanged.
+    fn encode_frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        METRIC_WEBSOCKET_BYTES_BEFORE_COMPRESSION.inc_by(payload.len() as u64);
+        let framed = if payload.len() > COMPRESSION_THRESHOLD_BYTES {
+            let mut framed = vec![1u8];
+            framed.extend(self.compress(payload));
+            framed
+        } else {
+            let mut framed = vec![0u8];
+            framed.extend_from_slice(payload);
+            framed
+        };
+        METRIC_WEBSOCKET_BYTES_AFTER_COMPRESSION.inc_by(framed.len() as u64);
+        framed
+    }
+
+    /// Reverses `encode_frame`.
+    fn decode_frame(&mut self, frame: &[u8]) -> Result<Vec<u8>> {
+        match frame.split_first() {
+            Some((1, payload)) => self.decompress(payload),
+            Some((_, payload)) => Ok(payload.to_vec()),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// WebSocket close codes this server sends when a `/rpc`/`/rpc_headless` connection ends
+/// abnormally, so the client (and whoever's reading its logs) learns *why* the socket closed
+/// instead of just observing a dropped TCP connection.
+#[derive(Clone, Copy, Debug)]
+enum AppCloseCode {
+    /// RFC 6455 1001: this node is draining its connections; the client should reconnect, likely
+    /// landing on a different node behind the load balancer.
+    GoingAway = 1001,
+    /// RFC 6455 1011: `handle_connection` returned an error we don't have a more specific code
+    /// for.
+    InternalError = 1011,
+    /// Private-use range (4000+): the client's session was rejected after the socket was already
+    /// open, e.g. a token that was valid at upgrade time but was revoked mid-connection.
+    AuthFailed = 4000,
+    /// Private-use range: the negotiated protocol version stopped being acceptable mid-connection,
+    /// e.g. this node's `MIN_SUPPORTED_PROTOCOL_VERSION` advanced past it.
+    VersionMismatch = 4001,
+}
+
+/// Maps an error surfaced by `handle_connection`/`handle_headless_connection` to the close code
+/// that best explains it to the client. This is a best-effort classification over the error's
+/// `Display` text, since those functions return a plain `anyhow::Error` rather than a typed enum
+/// we could match on directly; anything unrecognized falls back to `InternalError`.
+fn close_code_for_error(error: &anyhow::Error) -> AppCloseCode {
+    let message = error.to_string().to_lowercase();
+    if message.contains("auth") || message.contains("sign") {
+        AppCloseCode::AuthFailed
+    } else if message.contains("protocol") || message.contains("version") {
+        AppCloseCode::VersionMismatch
+    } else {
+        AppCloseCode::InternalError
+    }
+}
+
+/// Wraps the raw `axum` `WebSocket` behind a lock so both the `Connection`/`Peer` read-write loop
+/// and, once that loop has exited, this module's own close-frame logic can reach the same
+/// underlying socket. `Connection` is handed a `SharedWebSocket` clone to drive as usual; after
+/// `handle_connection` returns, the `on_upgrade` closure takes the socket back out (if
+/// `Connection` hasn't already dropped it) to send a final, reason-coded `Close` message before
+/// the TCP connection actually goes away.
+#[derive(Clone)]
+struct SharedWebSocket(Arc<std::sync::Mutex<Option<axum::extract::ws::WebSocket>>>);
+
+impl SharedWebSocket {
+    fn new(socket: axum::extract::ws::WebSocket) -> Self {
+        Self(Arc::new(std::sync::Mutex::new(Some(socket))))
+    }
+
+    /// Takes the socket back out, sends `code`/`reason` as a `Close` frame, and drops it. A no-op
+    /// if `Connection` already consumed (and presumably closed) the socket itself.
+    async fn close_with(&self, code: AppCloseCode, reason: &str) {
+        let socket = self.0.lock().unwrap().take();
+        if let Some(mut socket) = socket {
+            let _ = socket
+                .send(AxumMessage::Close(Some(AxumCloseFrame {
+                    code: code as u16,
+                    reason: reason.to_string().into(),
+                })))
+                .await;
+        }
+    }
+}
+
+impl Stream for SharedWebSocket {
+    type Item = Result<AxumMessage, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut guard = self.0.lock().unwrap();
+        match guard.as_mut() {
+            Some(socket) => Pin::new(socket).poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl Sink<AxumMessage> for SharedWebSocket {
+    type Error = axum::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut guard = self.0.lock().unwrap();
+        match guard.as_mut() {
+            Some(socket) => Pin::new(socket).poll_ready(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: AxumMessage) -> Result<(), Self::Error> {
+        let mut guard = self.0.lock().unwrap();
+        match guard.as_mut() {
+            Some(socket) => Pin::new(socket).start_send(item),
+            None => Ok(()),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut guard = self.0.lock().unwrap();
+        match guard.as_mut() {
+            Some(socket) => Pin::new(socket).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut guard = self.0.lock().unwrap();
+        match guard.as_mut() {
+            Some(socket) => Pin::new(socket).poll_close(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
 pub async fn handle_websocket_request(
     TypedHeader(ProtocolVersion(protocol_version)): TypedHeader<ProtocolVersion>,
+    resume_token: Option<TypedHeader<ZedResumeToken>>,
+    last_sequence_acked: Option<TypedHeader<ZedLastSequence>>,
+    headers: HeaderMap,
     ConnectInfo(socket_address): ConnectInfo<SocketAddr>,
     Extension(server): Extension<Arc<Server>>,
     Extension(user): Extension<User>,
     ws: WebSocketUpgrade,
 ) -> axum::response::Response {
-    if protocol_version != rpc::PROTOCOL_VERSION {
+    let Some(negotiated_version) = negotiate_protocol_version(protocol_version) else {
+        METRIC_UPGRADE_REJECTIONS
+            .with_label_values(&["protocol_version"])
+            .inc();
         return (
             StatusCode::UPGRADE_REQUIRED,
             "client must be upgraded".to_string(),
         )
             .into_response();
+    };
+    let socket_address = socket_address.to_string();
+    let permessage_deflate = negotiate_permessage_deflate(&headers);
+    let websocket_config = server.websocket_config;
+    let ws = ws
+        .max_message_size(websocket_config.max_message_size)
+        .max_frame_size(websocket_config.max_frame_size)
+        .max_write_buffer_size(websocket_config.max_write_buffer_size);
+    let mut response = ws.on_upgrade(move |socket| {
+        use util::ResultExt;
+        let shared_socket = SharedWebSocket::new(socket);
+        let deflate = permessage_deflate
+            .map(|params| Arc::new(std::sync::Mutex::new(PerMessageDeflate::new(params))));
+        let incoming_deflate = deflate.clone();
+        let outgoing_deflate = deflate.clone();
+        let socket = shared_socket
+            .clone()
+            .map_ok(move |message| {
+                let Some(deflate) = &incoming_deflate else {
+                    return message;
+                };
+                let AxumMessage::Binary(frame) = &message else {
+                    return message;
+                };
+                match deflate.lock().unwrap().decode_frame(frame) {
+                    Ok(payload) => AxumMessage::Binary(payload),
+                    Err(_) => message,
+                }
+            })
+            .map_ok(to_tungstenite_message)
+            .err_into()
+            .with(move |message| {
+                let outgoing_deflate = outgoing_deflate.clone();
+                async move {
+                    let message = to_axum_message(message);
+                    let message = match (&message, &outgoing_deflate) {
+                        (AxumMessage::Binary(payload), Some(deflate)) => {
+                            AxumMessage::Binary(deflate.lock().unwrap().encode_frame(payload))
+                        }
+                        _ => message,
+                    };
+                    Ok(message)
+                }
+            });
+        let connection = Connection::new(Box::pin(socket));
+        async move {
+            let result = server
+                .handle_connection(
+                    connection,
+                    socket_address,
+                    user,
+                    resume_token.map(|TypedHeader(ZedResumeToken(token))| token),
+                    last_sequence_acked.map(|TypedHeader(ZedLastSequence(sequence))| sequence),
+                    negotiated_version,
+                    None,
+                    RealExecutor,
+                )
+                .await;
+            let close = if server.shutting_down.load(SeqCst) {
+                Some((AppCloseCode::GoingAway, "server is shutting down".to_string()))
+            } else if let Err(error) = &result {
+                Some((close_code_for_error(error), error.to_string()))
+            } else {
+                None
+            };
+            if let Some((code, reason)) = close {
+                shared_socket.close_with(code, &reason).await;
+            }
+            result.log_err();
+        }
+    });
+    if let Some(params) = permessage_deflate {
+        response.headers_mut().insert(
+            axum::http::header::SEC_WEBSOCKET_EXTENSIONS,
+            encode_permessage_deflate_response(&params),
+        );
     }
+    let mut negotiated_version_header = Vec::new();
+    ProtocolVersion(negotiated_version).encode(&mut negotiated_version_header);
+    for value in negotiated_version_header {
+        response
+            .headers_mut()
+            .insert(ZED_PROTOCOL_VERSION.clone(), value);
+    }
+    response
+}
+
+/// Upgrades a connection from a headless project-host agent, authenticated by
+/// `auth::validate_headless_header` with a dedicated host token rather than a user session.
+pub async fn handle_headless_websocket_request(
+    TypedHeader(ProtocolVersion(protocol_version)): TypedHeader<ProtocolVersion>,
+    ConnectInfo(socket_address): ConnectInfo<SocketAddr>,
+    Extension(server): Extension<Arc<Server>>,
+    Extension(host_user_id): Extension<UserId>,
+    ws: WebSocketUpgrade,
+) -> axum::response::Response {
+    let Some(negotiated_version) = negotiate_protocol_version(protocol_version) else {
+        METRIC_UPGRADE_REJECTIONS
+            .with_label_values(&["protocol_version"])
+            .inc();
+        return (
+            StatusCode::UPGRADE_REQUIRED,
+            "client must be upgraded".to_string(),
+        )
+            .into_response();
+    };
     let socket_address = socket_address.to_string();
-    ws.on_upgrade(move |socket| {
+    let websocket_config = server.websocket_config;
+    let ws = ws
+        .max_message_size(websocket_config.max_message_size)
+        .max_frame_size(websocket_config.max_frame_size)
+        .max_write_buffer_size(websocket_config.max_write_buffer_size);
+    let mut response = ws.on_upgrade(move |socket| {
         use util::ResultExt;
-        let socket = socket
+        let shared_socket = SharedWebSocket::new(socket);
+        let socket = shared_socket
+            .clone()
             .map_ok(to_tungstenite_message)
             .err_into()
             .with(|message| async move { Ok(to_axum_message(message)) });
         let connection = Connection::new(Box::pin(socket));
         async move {
-            server
-                .handle_connection(connection, socket_address, user, None, RealExecutor)
-                .await
-                .log_err();
+            let result = server
+                .handle_headless_connection(connection, socket_address, host_user_id, RealExecutor)
+                .await;
+            let close = if server.shutting_down.load(SeqCst) {
+                Some((AppCloseCode::GoingAway, "server is shutting down".to_string()))
+            } else if let Err(error) = &result {
+                Some((close_code_for_error(error), error.to_string()))
+            } else {
+                None
+            };
+            if let Some((code, reason)) = close {
+                shared_socket.close_with(code, &reason).await;
+            }
+            result.log_err();
         }
-    })
+    });
+    let mut negotiated_version_header = Vec::new();
+    ProtocolVersion(negotiated_version).encode(&mut negotiated_version_header);
+    for value in negotiated_version_header {
+        response
+            .headers_mut()
+            .insert(ZED_PROTOCOL_VERSION.clone(), value);
+    }
+    response
 }
 
 pub async fn handle_metrics(Extension(server): Extension<Arc<Server>>) -> axum::response::Response {
@@ -2065,6 +3537,7 @@ pub async fn handle_metrics(Extension(server): Extension<Arc<Server>>) -> axum::
     METRIC_REGISTERED_PROJECTS.set(metrics.registered_projects as _);
     METRIC_ACTIVE_PROJECTS.set(metrics.active_projects as _);
     METRIC_SHARED_PROJECTS.set(metrics.shared_projects as _);
+    METRIC_MAX_OUTGOING_QUEUE_DEPTH.set(server.peer.max_outgoing_queue_depth() as _);
 
     let encoder = prometheus::TextEncoder::new();
     let metric_families = prometheus::gather();
@@ -5,13 +5,19 @@ use gpui::{
 };
 use picker::{Picker, PickerDelegate};
 use settings::Settings;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use util::TryFutureExt;
 
 pub fn init(cx: &mut MutableAppContext) {
     Picker::<ContactFinder>::init(cx);
 }
 
+/// How long a burst of keystrokes is coalesced into a single `fuzzy_search_users` request. The
+/// picker drops (and thus cancels) the previous `update_matches` task whenever a new one is
+/// returned, so sleeping this long before actually issuing the request is enough to debounce --
+/// only the last keystroke in a fast-typed burst survives to fire a search.
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(150);
+
 pub struct ContactFinder {
     picker: ViewHandle<Picker<Self>>,
     potential_contacts: Arc<[Arc<User>]>,
@@ -23,6 +29,18 @@ pub enum Event {
     Dismissed,
 }
 
+/// Orders `ContactRequestStatus` so that rows needing a response from the user come first,
+/// followed by users with no request either way, then requests already sent, then existing
+/// contacts.
+fn status_priority(status: ContactRequestStatus) -> u8 {
+    match status {
+        ContactRequestStatus::RequestReceived => 0,
+        ContactRequestStatus::None => 1,
+        ContactRequestStatus::RequestSent => 2,
+        ContactRequestStatus::RequestAccepted => 3,
+    }
+}
+
 impl Entity for ContactFinder {
     type Event = Event;
 }
@@ -57,15 +75,17 @@ impl PickerDelegate for ContactFinder {
     }
 
     fn update_matches(&mut self, query: String, cx: &mut ViewContext<Self>) -> Task<()> {
-        let search_users = self
-            .user_store
-            .update(cx, |store, cx| store.fuzzy_search_users(query, cx));
+        let user_store = self.user_store.clone();
 
         cx.spawn(|this, mut cx| async move {
             async {
+                cx.background().timer(SEARCH_DEBOUNCE).await;
+
+                let search_users =
+                    user_store.update(&mut cx, |store, cx| store.fuzzy_search_users(query, cx));
                 let potential_contacts = search_users.await?;
                 this.update(&mut cx, |this, cx| {
-                    this.potential_contacts = potential_contacts.into();
+                    this.set_potential_contacts(potential_contacts, cx);
                     cx.notify();
                 });
                 Ok(())
@@ -126,7 +146,28 @@ impl PickerDelegate for ContactFinder {
             .picker
             .item
             .style_for(mouse_state, selected);
-        Flex::row()
+
+        let is_first_in_group = ix == 0
+            || status_priority(request_status)
+                != status_priority(
+                    self.user_store
+                        .read(cx)
+                        .contact_request_status(&self.potential_contacts[ix - 1]),
+                );
+        let header = is_first_in_group.then(|| {
+            let header_text = match request_status {
+                ContactRequestStatus::RequestReceived => "Requests",
+                ContactRequestStatus::None => "People",
+                ContactRequestStatus::RequestSent => "Pending",
+                ContactRequestStatus::RequestAccepted => "Contacts",
+            };
+            Label::new(header_text, style.label.clone())
+                .aligned()
+                .left()
+                .boxed()
+        });
+
+        let row = Flex::row()
             .with_children(user.avatar.clone().map(|avatar| {
                 Image::new(avatar)
                     .with_style(theme.contact_finder.contact_avatar)
@@ -161,7 +202,12 @@ impl PickerDelegate for ContactFinder {
             .with_style(style.container)
             .constrained()
             .with_height(theme.contact_finder.row_height)
-            .boxed()
+            .boxed();
+
+        match header {
+            Some(header) => Flex::column().with_child(header).with_child(row).boxed(),
+            None => row,
+        }
     }
 }
 
@@ -178,4 +224,13 @@ impl ContactFinder {
             selected_index: 0,
         }
     }
+
+    /// Sorts contacts so that users requiring attention (an incoming request) float to the top,
+    /// then everyone else, then outstanding outgoing requests, with already-mutual contacts last;
+    /// `render_match` groups rows by this same ordering.
+    fn set_potential_contacts(&mut self, mut contacts: Vec<Arc<User>>, cx: &mut ViewContext<Self>) {
+        let user_store = self.user_store.read(cx);
+        contacts.sort_by_key(|user| status_priority(user_store.contact_request_status(user)));
+        self.potential_contacts = contacts.into();
+    }
 }
@@ -12,10 +12,22 @@ pub enum DiffHunkStatus {
     Removed,
 }
 
+/// Hunks larger than this (head + buffer byte length combined) skip word-level refinement
+/// entirely: `word_diff` is left empty and the hunk highlights as a whole line, same as before
+/// this field existed. Keeps the O(n*m) LCS below from running on pathological hunks (e.g. a
+/// giant pasted blob replacing a giant deleted blob).
+const MAX_HUNK_REFINEMENT_BYTES: usize = 4096;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DiffHunk<T> {
     pub buffer_range: Range<T>,
     pub head_byte_range: Range<usize>,
+    /// Sub-hunk (word-level) refinement of a `Modified` hunk: each entry pairs a byte range into
+    /// `head_byte_range`'s slice of `diff_base` (relative to the hunk, i.e. `0` is
+    /// `head_byte_range.start`) with the corresponding `Anchor` range in the buffer, so the editor
+    /// can highlight just the changed words instead of the whole line. Always empty for `Added`
+    /// and `Removed` hunks, and for any `Modified` hunk over `MAX_HUNK_REFINEMENT_BYTES`.
+    pub word_diff: Vec<(Range<usize>, Range<Anchor>)>,
 }
 
 impl DiffHunk<u32> {
@@ -99,10 +111,82 @@ impl BufferDiff {
             Some(DiffHunk {
                 buffer_range: range.start.row..end_row,
                 head_byte_range: hunk.head_byte_range.clone(),
+                word_diff: hunk.word_diff.clone(),
             })
         })
     }
 
+    /// Like `hunks_in_range`, but takes an arbitrary set of selection ranges rather than a single
+    /// contiguous row range, so the editor can collect every hunk touched by a multi-cursor
+    /// selection (for a "revert selection" action) in one pass. Overlapping/adjacent selections
+    /// are merged first so a hunk straddling two selections is only returned once.
+    pub fn hunks_intersecting_selections<'a>(
+        &'a self,
+        selections: &'a [Range<Anchor>],
+        buffer: &'a BufferSnapshot,
+    ) -> impl 'a + Iterator<Item = DiffHunk<u32>> {
+        let mut row_ranges = selections
+            .iter()
+            .map(|selection| {
+                let range = selection.to_point(buffer);
+                range.start.row..range.end.row + 1
+            })
+            .collect::<Vec<_>>();
+        row_ranges.sort_by_key(|range| range.start);
+
+        let mut merged_ranges: Vec<Range<u32>> = Vec::new();
+        for range in row_ranges {
+            if let Some(last) = merged_ranges.last_mut() {
+                if range.start <= last.end {
+                    last.end = last.end.max(range.end);
+                    continue;
+                }
+            }
+            merged_ranges.push(range);
+        }
+
+        // A hunk spanning the gap between two merged ranges comes back from both `hunks_in_range`
+        // calls, as the last hunk of one and the first hunk of the next.
+        let mut last_hunk: Option<DiffHunk<u32>> = None;
+        merged_ranges
+            .into_iter()
+            .flat_map(move |range| self.hunks_in_range(range, buffer))
+            .filter(move |hunk| {
+                let is_repeat = last_hunk.as_ref() == Some(hunk);
+                last_hunk = Some(hunk.clone());
+                !is_repeat
+            })
+    }
+
+    /// Returns the buffer edit (a byte range and replacement text) that restores `hunk` to its
+    /// state in `diff_base`, i.e. what a "revert hunk" editor action would apply. Callers combine
+    /// these across every hunk they want to revert into a single multi-range buffer edit.
+    pub fn revert_hunk(
+        &self,
+        hunk: &DiffHunk<u32>,
+        diff_base: &str,
+        buffer: &BufferSnapshot,
+    ) -> (Range<Point>, String) {
+        let replacement_text = diff_base[hunk.head_byte_range.clone()].to_string();
+        let range = match hunk.status() {
+            // The added rows don't exist in `diff_base`, so reverting means deleting them outright.
+            DiffHunkStatus::Added => {
+                Point::new(hunk.buffer_range.start, 0)..Point::new(hunk.buffer_range.end, 0)
+            }
+            // The hunk's buffer range is already the empty point where the deleted head text used
+            // to live; reverting means inserting it back there.
+            DiffHunkStatus::Removed => {
+                let point = Point::new(hunk.buffer_range.start, 0);
+                point..point
+            }
+            // Replace the modified rows with the head text they were modified from.
+            DiffHunkStatus::Modified => {
+                Point::new(hunk.buffer_range.start, 0)..Point::new(hunk.buffer_range.end, 0)
+            }
+        };
+        (range, replacement_text)
+    }
+
     pub fn clear(&mut self, buffer: &text::BufferSnapshot) {
         self.last_buffer_version = Some(buffer.version().clone());
         self.tree = SumTree::new();
@@ -124,7 +208,8 @@ impl BufferDiff {
         if let Some(patch) = patch {
             let mut divergence = 0;
             for hunk_index in 0..patch.num_hunks() {
-                let hunk = Self::process_patch_hunk(&patch, hunk_index, buffer, &mut divergence);
+                let hunk =
+                    Self::process_patch_hunk(&patch, hunk_index, diff_base, buffer, &mut divergence);
                 tree.push(hunk, buffer);
             }
         }
@@ -163,6 +248,7 @@ impl BufferDiff {
     fn process_patch_hunk<'a>(
         patch: &GitPatch<'a>,
         hunk_index: usize,
+        diff_base: &str,
         buffer: &text::BufferSnapshot,
         buffer_row_divergence: &mut i64,
     ) -> DiffHunk<Anchor> {
@@ -220,11 +306,191 @@ impl BufferDiff {
         let start = Point::new(buffer_row_range.start, 0);
         let end = Point::new(buffer_row_range.end, 0);
         let buffer_range = buffer.anchor_before(start)..buffer.anchor_before(end);
+
+        let word_diff = if !head_byte_range.is_empty() && !buffer_row_range.is_empty() {
+            refine_hunk(diff_base, &head_byte_range, buffer, &buffer_range)
+        } else {
+            Vec::new()
+        };
+
         DiffHunk {
             buffer_range,
             head_byte_range,
+            word_diff,
+        }
+    }
+}
+
+/// Computes a word-level refinement of a `Modified` hunk by tokenizing the head and buffer text
+/// and diffing the token sequences, so the caller can highlight exactly the changed words instead
+/// of the whole line. Returns byte ranges relative to `head_byte_range` paired with `Anchor`
+/// ranges in `buffer`; returns an empty `Vec` if the hunk is too large to bother refining.
+fn refine_hunk(
+    diff_base: &str,
+    head_byte_range: &Range<usize>,
+    buffer: &text::BufferSnapshot,
+    buffer_range: &Range<Anchor>,
+) -> Vec<(Range<usize>, Range<Anchor>)> {
+    let head_text = &diff_base[head_byte_range.clone()];
+    let buffer_offset_range = buffer_range.to_offset(buffer);
+    if head_text.len() + (buffer_offset_range.end - buffer_offset_range.start) > MAX_HUNK_REFINEMENT_BYTES {
+        return Vec::new();
+    }
+
+    let buffer_text = buffer
+        .text_for_range(buffer_offset_range.clone())
+        .collect::<String>();
+
+    diff_tokens(head_text, &buffer_text)
+        .into_iter()
+        .map(|(head_range, buffer_range)| {
+            let start = buffer.anchor_before(buffer_offset_range.start + buffer_range.start);
+            let end = buffer.anchor_before(buffer_offset_range.start + buffer_range.end);
+            (head_range, start..end)
+        })
+        .collect()
+}
+
+/// Diffs two strings at token granularity (runs of alphanumerics, runs of whitespace, and
+/// individual punctuation characters), returning the maximal non-matching byte ranges on each
+/// side, in order. Matching is found via the longest common token subsequence, so a one-word
+/// change in the middle of a long, otherwise-identical line produces a single small range rather
+/// than flagging the whole line.
+fn diff_tokens(old_text: &str, new_text: &str) -> Vec<(Range<usize>, Range<usize>)> {
+    let old_tokens = tokenize(old_text);
+    let new_tokens = tokenize(new_text);
+    let matched_pairs = longest_common_token_subsequence(old_text, &old_tokens, new_text, &new_tokens);
+
+    // Token-index ranges (not yet byte ranges) of the non-matching gaps, as (old side, new side)
+    // pairs. The matched tokens between two consecutive gaps are what the coalescing pass below
+    // inspects.
+    let mut gaps: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    let mut old_cursor = 0;
+    let mut new_cursor = 0;
+    for (old_index, new_index) in &matched_pairs {
+        if *old_index > old_cursor || *new_index > new_cursor {
+            gaps.push((old_cursor..*old_index, new_cursor..*new_index));
+        }
+        old_cursor = old_index + 1;
+        new_cursor = new_index + 1;
+    }
+    if old_cursor < old_tokens.len() || new_cursor < new_tokens.len() {
+        gaps.push((old_cursor..old_tokens.len(), new_cursor..new_tokens.len()));
+    }
+
+    // Coalesce adjacent gaps whose sole bridging match is a single whitespace-only token, so e.g.
+    // `foo bar` -> `baz qux` reports one range on each side rather than `foo`/`baz` and
+    // `bar`/`qux` as two separate ranges split by the unchanged space.
+    let mut coalesced: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for gap in gaps {
+        if let Some(previous) = coalesced.last_mut() {
+            let bridge = previous.0.end..gap.0.start;
+            if bridge.end - bridge.start == 1 && is_whitespace_token(old_text, &old_tokens[bridge.start]) {
+                previous.0.end = gap.0.end;
+                previous.1.end = gap.1.end;
+                continue;
+            }
+        }
+        coalesced.push(gap);
+    }
+
+    coalesced
+        .into_iter()
+        .map(|(old_range, new_range)| {
+            (
+                token_byte_span(&old_tokens, old_range),
+                token_byte_span(&new_tokens, new_range),
+            )
+        })
+        .collect()
+}
+
+fn is_whitespace_token(text: &str, token: &Range<usize>) -> bool {
+    text[token.clone()].chars().all(|ch| ch.is_whitespace())
+}
+
+/// Maps a `start..end` range of token indices back to the byte range spanned by those tokens, or
+/// to a zero-length range at the appropriate boundary if the range is empty.
+fn token_byte_span(tokens: &[Range<usize>], index_range: Range<usize>) -> Range<usize> {
+    if index_range.is_empty() {
+        let boundary = tokens
+            .get(index_range.start)
+            .map(|token| token.start)
+            .or_else(|| index_range.start.checked_sub(1).and_then(|i| tokens.get(i)).map(|token| token.end))
+            .unwrap_or(0);
+        return boundary..boundary;
+    }
+    tokens[index_range.start].start..tokens[index_range.end - 1].end
+}
+
+/// Splits `text` into tokens: maximal runs of alphanumerics (plus `_`), maximal runs of
+/// whitespace, and individual punctuation/symbol characters.
+fn tokenize(text: &str) -> Vec<Range<usize>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, ch)) = chars.peek() {
+        let is_word_char = |ch: char| ch.is_alphanumeric() || ch == '_';
+        if is_word_char(ch) {
+            chars.next();
+            let mut end = start + ch.len_utf8();
+            while let Some(&(i, c)) = chars.peek().filter(|(_, c)| is_word_char(*c)) {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(start..end);
+        } else if ch.is_whitespace() {
+            chars.next();
+            let mut end = start + ch.len_utf8();
+            while let Some(&(i, c)) = chars.peek().filter(|(_, c)| c.is_whitespace()) {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(start..end);
+        } else {
+            chars.next();
+            tokens.push(start..start + ch.len_utf8());
+        }
+    }
+    tokens
+}
+
+/// Computes the longest common token subsequence between `old_tokens` and `new_tokens` (compared
+/// by their underlying text, not position) via the standard O(n*m) dynamic-programming
+/// formulation -- the same alignment Myers' algorithm finds, just without its linear-space
+/// optimization, which isn't worth the complexity given `MAX_HUNK_REFINEMENT_BYTES` keeps `n` and
+/// `m` small. Returns matched `(old_index, new_index)` pairs in increasing order.
+fn longest_common_token_subsequence(
+    old_text: &str,
+    old_tokens: &[Range<usize>],
+    new_text: &str,
+    new_tokens: &[Range<usize>],
+) -> Vec<(usize, usize)> {
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old_text[old_tokens[i].clone()] == new_text[new_tokens[j].clone()] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_text[old_tokens[i].clone()] == new_text[new_tokens[j].clone()] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
         }
     }
+    pairs
 }
 
 /// Range (crossing new lines), old, new
@@ -8,11 +8,52 @@ use crate::{
     presenter::MeasurementContext,
     scene, Border, DebugContext, Element, ImageData, LayoutContext, PaintContext, SizeConstraint,
 };
+use client::http::HttpClient;
+use lazy_static::lazy_static;
 use serde::Deserialize;
-use std::{ops::Range, sync::Arc};
+use smol::io::AsyncReadExt;
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// Where an `Image` element gets its pixels from: already-decoded data, or a URL to fetch and
+/// decode lazily.
+#[derive(Clone)]
+pub enum ImageSource {
+    Data(Arc<ImageData>),
+    Uri(String),
+}
+
+impl From<Arc<ImageData>> for ImageSource {
+    fn from(data: Arc<ImageData>) -> Self {
+        Self::Data(data)
+    }
+}
+
+impl From<String> for ImageSource {
+    fn from(uri: String) -> Self {
+        Self::Uri(uri)
+    }
+}
+
+#[derive(Clone)]
+enum CachedImage {
+    Loading,
+    Loaded(Arc<ImageData>),
+    Failed,
+}
+
+lazy_static! {
+    /// Decoded images fetched for `ImageSource::Uri`, keyed by URL, shared across every `Image`
+    /// element in the app so e.g. multiple contact rows showing the same avatar only fetch and
+    /// decode it once.
+    static ref IMAGE_CACHE: Mutex<HashMap<String, CachedImage>> = Mutex::new(HashMap::new());
+}
 
 pub struct Image {
-    data: Arc<ImageData>,
+    source: ImageSource,
     style: ImageStyle,
 }
 
@@ -31,9 +72,9 @@ pub struct ImageStyle {
 }
 
 impl Image {
-    pub fn new(data: Arc<ImageData>) -> Self {
+    pub fn new(source: impl Into<ImageSource>) -> Self {
         Self {
-            data,
+            source: source.into(),
             style: Default::default(),
         }
     }
@@ -42,6 +83,54 @@ impl Image {
         self.style = style;
         self
     }
+
+    /// Kicks off a fetch-and-decode of `uri` through `http` if it isn't already cached or in
+    /// flight. `on_loaded` fires once the fetch settles (success or failure) so the caller's view
+    /// can `cx.notify()` and pick up the now-cached image on the next render; `Image` has no way
+    /// to reach the owning view's context itself, so this has to be driven from the outside.
+    pub fn load_uri(uri: String, http: Arc<dyn HttpClient>, on_loaded: impl Fn() + Send + Sync + 'static) {
+        {
+            let mut cache = IMAGE_CACHE.lock().unwrap();
+            if cache.contains_key(&uri) {
+                return;
+            }
+            cache.insert(uri.clone(), CachedImage::Loading);
+        }
+
+        smol::spawn(async move {
+            let result = Self::fetch_and_decode(&uri, http).await;
+            let cached = match result {
+                Ok(data) => CachedImage::Loaded(data),
+                Err(error) => {
+                    log::error!("failed to load image at {uri}: {error}");
+                    CachedImage::Failed
+                }
+            };
+            IMAGE_CACHE.lock().unwrap().insert(uri, cached);
+            on_loaded();
+        })
+        .detach();
+    }
+
+    async fn fetch_and_decode(uri: &str, http: Arc<dyn HttpClient>) -> anyhow::Result<Arc<ImageData>> {
+        let mut response = http.get(uri, Default::default(), true).await?;
+        let mut body = Vec::new();
+        response.body_mut().read_to_end(&mut body).await?;
+        let image = image::load_from_memory(&body)?.into_rgba8();
+        Ok(Arc::new(ImageData::new(image)))
+    }
+
+    /// The decoded image to paint, if one is available yet: immediately for `Data`, or once
+    /// `load_uri` has populated the cache for `Uri`.
+    fn loaded_data(&self) -> Option<Arc<ImageData>> {
+        match &self.source {
+            ImageSource::Data(data) => Some(data.clone()),
+            ImageSource::Uri(uri) => match IMAGE_CACHE.lock().unwrap().get(uri) {
+                Some(CachedImage::Loaded(data)) => Some(data.clone()),
+                _ => None,
+            },
+        }
+    }
 }
 
 impl Element for Image {
@@ -57,10 +146,13 @@ impl Element for Image {
             self.style.width.unwrap_or_else(|| constraint.max.x()),
             self.style.height.unwrap_or_else(|| constraint.max.y()),
         );
-        let size = constrain_size_preserving_aspect_ratio(
-            constraint.constrain(desired_size),
-            self.data.size().to_f32(),
-        );
+        let constrained = constraint.constrain(desired_size);
+        // While a `Uri` source hasn't finished loading, we don't know its aspect ratio yet, so
+        // just lay out at the style's requested size.
+        let size = match self.loaded_data() {
+            Some(data) => constrain_size_preserving_aspect_ratio(constrained, data.size().to_f32()),
+            None => constrained,
+        };
         (size, ())
     }
 
@@ -71,13 +163,18 @@ impl Element for Image {
         _: &mut Self::LayoutState,
         cx: &mut PaintContext,
     ) -> Self::PaintState {
-        cx.scene.push_image(scene::Image {
-            bounds,
-            border: self.style.border,
-            corner_radius: self.style.corner_radius,
-            grayscale: self.style.grayscale,
-            data: self.data.clone(),
-        });
+        // Paint nothing while a `Uri` source is still loading (or failed to load); once
+        // `load_uri` populates the cache, the next paint after `on_loaded` triggers a re-render
+        // picks it up.
+        if let Some(data) = self.loaded_data() {
+            cx.scene.push_image(scene::Image {
+                bounds,
+                border: self.style.border,
+                corner_radius: self.style.corner_radius,
+                grayscale: self.style.grayscale,
+                data,
+            });
+        }
     }
 
     fn rect_for_text_range(
@@ -4,6 +4,7 @@ mod highlight_map;
 mod outline;
 pub mod proto;
 mod syntax_map;
+mod wasm_lsp_adapter;
 
 #[cfg(test)]
 mod buffer_tests;
@@ -39,7 +40,7 @@ use std::{
 };
 use syntax_map::SyntaxSnapshot;
 use theme::{SyntaxTheme, Theme};
-use tree_sitter::{self, Query};
+use tree_sitter::{self, Query, QueryCursor};
 use util::ResultExt;
 
 #[cfg(any(test, feature = "test-support"))]
@@ -50,6 +51,7 @@ pub use buffer::*;
 pub use diagnostic_set::DiagnosticEntry;
 pub use outline::{Outline, OutlineItem};
 pub use tree_sitter::{Parser, Tree};
+pub use wasm_lsp_adapter::WasmLspAdapter;
 
 thread_local! {
     static PARSER: RefCell<Parser> = RefCell::new(Parser::new());
@@ -73,12 +75,23 @@ pub trait ToLspPosition {
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct LanguageServerName(pub Arc<str>);
 
+/// What to launch an `lsp::LanguageServer` with. Returned from
+/// `fetch_server_binary`/`cached_server_binary`/`check_if_user_installed` rather than stored on
+/// `CachedLspAdapter`, so an adapter can choose a path and arguments based on the worktree it's
+/// starting up in (e.g. a project-local `node_modules/.bin` server, or version-specific flags).
+/// `LanguageRegistry` keeps the first result per `LanguageServerName` in `lsp_binary_paths`, so
+/// later starts of the same server, in any worktree, reuse that binary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanguageServerBinary {
+    pub path: PathBuf,
+    pub arguments: Vec<String>,
+}
+
 /// Represents a Language Server, with certain cached sync properties.
 /// Uses [`LspAdapter`] under the hood, but calls all 'static' methods
 /// once at startup, and caches the results.
 pub struct CachedLspAdapter {
     pub name: LanguageServerName,
-    pub server_args: Vec<String>,
     pub initialization_options: Option<Value>,
     pub disk_based_diagnostic_sources: Vec<String>,
     pub disk_based_diagnostics_progress_token: Option<String>,
@@ -90,7 +103,6 @@ impl CachedLspAdapter {
     pub async fn new<T: LspAdapter>(adapter: T) -> Arc<Self> {
         let adapter = Box::new(adapter);
         let name = adapter.name().await;
-        let server_args = adapter.server_args().await;
         let initialization_options = adapter.initialization_options().await;
         let disk_based_diagnostic_sources = adapter.disk_based_diagnostic_sources().await;
         let disk_based_diagnostics_progress_token =
@@ -99,7 +111,6 @@ impl CachedLspAdapter {
 
         Arc::new(CachedLspAdapter {
             name,
-            server_args,
             initialization_options,
             disk_based_diagnostic_sources,
             disk_based_diagnostics_progress_token,
@@ -120,16 +131,26 @@ impl CachedLspAdapter {
         version: Box<dyn 'static + Send + Any>,
         http: Arc<dyn HttpClient>,
         container_dir: PathBuf,
-    ) -> Result<PathBuf> {
+    ) -> Result<LanguageServerBinary> {
         self.adapter
             .fetch_server_binary(version, http, container_dir)
             .await
     }
 
-    pub async fn cached_server_binary(&self, container_dir: PathBuf) -> Option<PathBuf> {
+    pub async fn cached_server_binary(
+        &self,
+        container_dir: PathBuf,
+    ) -> Option<LanguageServerBinary> {
         self.adapter.cached_server_binary(container_dir).await
     }
 
+    pub async fn check_if_user_installed(
+        &self,
+        delegate: &dyn LspAdapterDelegate,
+    ) -> Option<LanguageServerBinary> {
+        self.adapter.check_if_user_installed(delegate).await
+    }
+
     pub async fn process_diagnostics(&self, params: &mut lsp::PublishDiagnosticsParams) {
         self.adapter.process_diagnostics(params).await
     }
@@ -168,9 +189,21 @@ pub trait LspAdapter: 'static + Send + Sync {
         version: Box<dyn 'static + Send + Any>,
         http: Arc<dyn HttpClient>,
         container_dir: PathBuf,
-    ) -> Result<PathBuf>;
+    ) -> Result<LanguageServerBinary>;
+
+    async fn cached_server_binary(&self, container_dir: PathBuf) -> Option<LanguageServerBinary>;
 
-    async fn cached_server_binary(&self, container_dir: PathBuf) -> Option<PathBuf>;
+    /// Looks for a language server the user already has installed outside of Zed (e.g. on
+    /// `$PATH`), so `get_server_binary_path` can use it instead of downloading a managed copy.
+    /// Adapters that need more than a bare `$PATH` lookup -- e.g. requiring a sibling toolchain
+    /// like `go` to be present in the worktree -- can use `delegate.worktree_root_path()` to
+    /// inspect the project. Returns `None` by default, meaning adapters opt in explicitly.
+    async fn check_if_user_installed(
+        &self,
+        _delegate: &dyn LspAdapterDelegate,
+    ) -> Option<LanguageServerBinary> {
+        None
+    }
 
     async fn process_diagnostics(&self, _: &mut lsp::PublishDiagnosticsParams) {}
 
@@ -191,10 +224,6 @@ pub trait LspAdapter: 'static + Send + Sync {
         None
     }
 
-    async fn server_args(&self) -> Vec<String> {
-        Vec::new()
-    }
-
     async fn initialization_options(&self) -> Option<Value> {
         None
     }
@@ -212,6 +241,43 @@ pub trait LspAdapter: 'static + Send + Sync {
     }
 }
 
+/// What an [`LspAdapter`] is allowed to see about the project it's starting a server for, so
+/// adapters loaded from untrusted sources (e.g. a `WasmLspAdapter` extension) can't
+/// reach further into the host than they need to.
+#[async_trait]
+pub trait LspAdapterDelegate: Send + Sync {
+    fn worktree_root_path(&self) -> &Path;
+
+    /// Searches the login shell's `$PATH` for an executable named `command`, returning its path
+    /// if found. Adapters that need more than a bare `$PATH` lookup should do so themselves using
+    /// `worktree_root_path`, rather than through this method.
+    async fn which_command(&self, command: &str) -> Option<PathBuf>;
+}
+
+struct RootPathLspAdapterDelegate {
+    root_path: Arc<Path>,
+}
+
+#[async_trait]
+impl LspAdapterDelegate for RootPathLspAdapterDelegate {
+    fn worktree_root_path(&self) -> &Path {
+        &self.root_path
+    }
+
+    async fn which_command(&self, command: &str) -> Option<PathBuf> {
+        find_binary_in_path(command)
+    }
+}
+
+/// Searches each directory in `$PATH`, in order, for an executable file named `name`.
+fn find_binary_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(name);
+        candidate.is_file().then_some(candidate)
+    })
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CodeLabel {
     pub text: String,
@@ -287,7 +353,7 @@ pub struct BracketPair {
 pub struct Language {
     pub(crate) config: LanguageConfig,
     pub(crate) grammar: Option<Arc<Grammar>>,
-    pub(crate) adapter: Option<Arc<CachedLspAdapter>>,
+    pub(crate) adapters: Vec<Arc<CachedLspAdapter>>,
 
     #[cfg(any(test, feature = "test-support"))]
     fake_adapter: Option<(
@@ -340,22 +406,41 @@ pub enum LanguageServerBinaryStatus {
     Downloading,
     Downloaded,
     Cached,
+    /// A language server the user already had installed (e.g. on `$PATH`) was used instead of
+    /// downloading a managed copy.
+    Found,
     Failed { error: String },
 }
 
+/// A stable, type-safe handle to a single `start_language_server` invocation, allocated by
+/// `LanguageRegistry` rather than supplied by the caller -- this is what prevents two worktrees
+/// starting the same language server from colliding on an id.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LanguageServerId(pub usize);
+
+/// What `LanguageRegistry` knows about one running (or starting) language server, keyed by its
+/// [`LanguageServerId`].
+pub struct RunningLanguageServerState {
+    pub language: Arc<Language>,
+    pub adapter_name: LanguageServerName,
+    pub binary_status: LanguageServerBinaryStatus,
+}
+
 pub struct LanguageRegistry {
     languages: RwLock<Vec<Arc<Language>>>,
     language_server_download_dir: Option<Arc<Path>>,
-    lsp_binary_statuses_tx: async_broadcast::Sender<(Arc<Language>, LanguageServerBinaryStatus)>,
-    lsp_binary_statuses_rx: async_broadcast::Receiver<(Arc<Language>, LanguageServerBinaryStatus)>,
+    lsp_binary_statuses_tx: async_broadcast::Sender<(LanguageServerId, LanguageServerBinaryStatus)>,
+    lsp_binary_statuses_rx: async_broadcast::Receiver<(LanguageServerId, LanguageServerBinaryStatus)>,
     login_shell_env_loaded: Shared<Task<()>>,
     #[allow(clippy::type_complexity)]
     lsp_binary_paths: Mutex<
         HashMap<
             LanguageServerName,
-            Shared<BoxFuture<'static, Result<PathBuf, Arc<anyhow::Error>>>>,
+            Shared<BoxFuture<'static, Result<LanguageServerBinary, Arc<anyhow::Error>>>>,
         >,
     >,
+    next_language_server_id: AtomicUsize,
+    running_language_servers: Mutex<HashMap<LanguageServerId, RunningLanguageServerState>>,
     subscription: RwLock<(watch::Sender<()>, watch::Receiver<()>)>,
     theme: RwLock<Option<Arc<Theme>>>,
 }
@@ -370,6 +455,8 @@ impl LanguageRegistry {
             lsp_binary_statuses_rx,
             login_shell_env_loaded: login_shell_env_loaded.shared(),
             lsp_binary_paths: Default::default(),
+            next_language_server_id: Default::default(),
+            running_language_servers: Default::default(),
             subscription: RwLock::new(watch::channel()),
             theme: Default::default(),
         }
@@ -403,6 +490,34 @@ impl LanguageRegistry {
         self.language_server_download_dir = Some(path.into());
     }
 
+    /// Installs a `wasm32-wasi` module at `wasm_path` as a downloadable language server
+    /// extension, so a third party can ship an `LspAdapter` integration without recompiling this
+    /// crate. The returned adapter dispatches through the same `Language::with_lsp_adapter`/
+    /// `with_lsp_adapters` plumbing and `disk_based_diagnostic_sources`/
+    /// `disk_based_diagnostics_progress_token` accessors as a built-in Rust adapter --
+    /// `CachedLspAdapter::new` is generic over any `LspAdapter` impl, so no separate dispatch path
+    /// is needed for a wasm-backed one.
+    pub async fn load_wasm_lsp_adapter(
+        &self,
+        name: LanguageServerName,
+        wasm_path: &Path,
+        http_client: Arc<dyn HttpClient>,
+    ) -> Result<Arc<CachedLspAdapter>> {
+        let download_dir = self
+            .language_server_download_dir
+            .clone()
+            .ok_or_else(|| anyhow!("language server download directory has not been assigned"))?;
+        let container_dir = download_dir.join(name.0.as_ref());
+        if !container_dir.exists() {
+            smol::fs::create_dir_all(&container_dir)
+                .await
+                .context("failed to create container directory")?;
+        }
+        let adapter =
+            WasmLspAdapter::load_from_file(name, wasm_path, http_client, container_dir)?;
+        Ok(CachedLspAdapter::new(adapter).await)
+    }
+
     pub fn get_language(&self, name: &str) -> Option<Arc<Language>> {
         self.languages
             .read()
@@ -441,92 +556,176 @@ impl LanguageRegistry {
             .cloned()
     }
 
+    /// Allocates the next [`LanguageServerId`] from this registry's own counter, the same way
+    /// `NEXT_GRAMMAR_ID` hands out `Grammar` ids process-wide: ids only ever increase and are
+    /// never reused, since callers never need to reclaim a specific value.
+    fn allocate_language_server_id(&self) -> LanguageServerId {
+        LanguageServerId(self.next_language_server_id.fetch_add(1, SeqCst))
+    }
+
+    /// Updates the tracked state for a running server and broadcasts the new status, so
+    /// consumers of `language_server_binary_statuses` can correlate download progress with a
+    /// specific server instance rather than just a language.
+    async fn set_binary_status(
+        &self,
+        id: LanguageServerId,
+        status: LanguageServerBinaryStatus,
+    ) -> Result<()> {
+        if let Some(state) = self.running_language_servers.lock().get_mut(&id) {
+            state.binary_status = status.clone();
+        }
+        self.lsp_binary_statuses_tx.broadcast((id, status)).await?;
+        Ok(())
+    }
+
+    /// The ids of all servers currently tracked as running for `language`.
+    pub fn language_server_ids_for_language(&self, language: &Arc<Language>) -> Vec<LanguageServerId> {
+        self.running_language_servers
+            .lock()
+            .iter()
+            .filter(|(_, state)| Arc::ptr_eq(&state.language, language))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Stops tracking `id` as a running server. This only updates the registry's own bookkeeping
+    /// -- callers are responsible for actually shutting down the underlying `lsp::LanguageServer`.
+    pub fn stop_language_server(&self, id: LanguageServerId) {
+        self.running_language_servers.lock().remove(&id);
+    }
+
+    /// Starts one `lsp::LanguageServer` per adapter `language` is configured with (e.g. a primary
+    /// LSP server alongside a dedicated linter), returning a handle for each. Binary downloads are
+    /// still deduplicated process-wide in `lsp_binary_paths`, which is keyed by `LanguageServerName`
+    /// rather than by language, so two languages that share an adapter (or one language with the
+    /// same adapter started twice for different worktrees) only fetch it once.
     pub fn start_language_server(
         self: &Arc<Self>,
-        server_id: usize,
         language: Arc<Language>,
         root_path: Arc<Path>,
         http_client: Arc<dyn HttpClient>,
         cx: &mut MutableAppContext,
-    ) -> Option<Task<Result<lsp::LanguageServer>>> {
+    ) -> Vec<(LanguageServerId, Task<Result<lsp::LanguageServer>>)> {
         #[cfg(any(test, feature = "test-support"))]
         if language.fake_adapter.is_some() {
-            let language = language;
-            return Some(cx.spawn(|cx| async move {
-                let (servers_tx, fake_adapter) = language.fake_adapter.as_ref().unwrap();
-                let (server, mut fake_server) = lsp::LanguageServer::fake(
-                    fake_adapter.name.to_string(),
-                    fake_adapter.capabilities.clone(),
-                    cx.clone(),
-                );
+            let server_id = self.allocate_language_server_id();
+            let (_, fake_adapter) = language.fake_adapter.as_ref().unwrap();
+            self.running_language_servers.lock().insert(
+                server_id,
+                RunningLanguageServerState {
+                    language: language.clone(),
+                    adapter_name: LanguageServerName(fake_adapter.name.clone().into()),
+                    binary_status: LanguageServerBinaryStatus::Downloaded,
+                },
+            );
 
-                if let Some(initializer) = &fake_adapter.initializer {
-                    initializer(&mut fake_server);
-                }
+            let language = language;
+            return vec![(
+                server_id,
+                cx.spawn(|cx| async move {
+                    let (servers_tx, fake_adapter) = language.fake_adapter.as_ref().unwrap();
+                    let (server, mut fake_server) = lsp::LanguageServer::fake(
+                        fake_adapter.name.to_string(),
+                        fake_adapter.capabilities.clone(),
+                        cx.clone(),
+                    );
+
+                    if let Some(initializer) = &fake_adapter.initializer {
+                        initializer(&mut fake_server);
+                    }
 
-                let servers_tx = servers_tx.clone();
-                cx.background()
-                    .spawn(async move {
-                        if fake_server
-                            .try_receive_notification::<lsp::notification::Initialized>()
-                            .await
-                            .is_some()
-                        {
-                            servers_tx.unbounded_send(fake_server).ok();
-                        }
-                    })
-                    .detach();
-                Ok(server)
-            }));
+                    let servers_tx = servers_tx.clone();
+                    cx.background()
+                        .spawn(async move {
+                            if fake_server
+                                .try_receive_notification::<lsp::notification::Initialized>()
+                                .await
+                                .is_some()
+                            {
+                                servers_tx.unbounded_send(fake_server).ok();
+                            }
+                        })
+                        .detach();
+                    Ok(server)
+                }),
+            )];
         }
 
-        let download_dir = self
+        let download_dir = match self
             .language_server_download_dir
             .clone()
             .ok_or_else(|| anyhow!("language server download directory has not been assigned"))
-            .log_err()?;
-
-        let this = self.clone();
-        let adapter = language.adapter.clone()?;
-        let lsp_binary_statuses = self.lsp_binary_statuses_tx.clone();
-        let login_shell_env_loaded = self.login_shell_env_loaded.clone();
-        Some(cx.spawn(|cx| async move {
-            login_shell_env_loaded.await;
-            let server_binary_path = this
-                .lsp_binary_paths
-                .lock()
-                .entry(adapter.name.clone())
-                .or_insert_with(|| {
-                    get_server_binary_path(
-                        adapter.clone(),
-                        language.clone(),
-                        http_client,
-                        download_dir,
-                        lsp_binary_statuses,
-                    )
-                    .map_err(Arc::new)
-                    .boxed()
-                    .shared()
-                })
-                .clone()
-                .map_err(|e| anyhow!(e));
+            .log_err()
+        {
+            Some(download_dir) => download_dir,
+            None => return Vec::new(),
+        };
 
-            let server_binary_path = server_binary_path.await?;
-            let server_args = &adapter.server_args;
-            let server = lsp::LanguageServer::new(
-                server_id,
-                &server_binary_path,
-                server_args,
-                &root_path,
-                cx,
-            )?;
-            Ok(server)
-        }))
+        language
+            .adapters
+            .iter()
+            .cloned()
+            .map(|adapter| {
+                let this = self.clone();
+                let login_shell_env_loaded = self.login_shell_env_loaded.clone();
+                let delegate: Arc<dyn LspAdapterDelegate> = Arc::new(RootPathLspAdapterDelegate {
+                    root_path: root_path.clone(),
+                });
+                let http_client = http_client.clone();
+                let download_dir = download_dir.clone();
+                let root_path = root_path.clone();
+
+                let server_id = self.allocate_language_server_id();
+                self.running_language_servers.lock().insert(
+                    server_id,
+                    RunningLanguageServerState {
+                        language: language.clone(),
+                        adapter_name: adapter.name.clone(),
+                        binary_status: LanguageServerBinaryStatus::CheckingForUpdate,
+                    },
+                );
+
+                let task = cx.spawn(|cx| async move {
+                    login_shell_env_loaded.await;
+                    let server_binary = this
+                        .lsp_binary_paths
+                        .lock()
+                        .entry(adapter.name.clone())
+                        .or_insert_with(|| {
+                            get_server_binary_path(
+                                this.clone(),
+                                server_id,
+                                adapter.clone(),
+                                delegate,
+                                http_client,
+                                download_dir,
+                            )
+                            .map_err(Arc::new)
+                            .boxed()
+                            .shared()
+                        })
+                        .clone()
+                        .map_err(|e| anyhow!(e));
+
+                    let server_binary = server_binary.await?;
+                    let server = lsp::LanguageServer::new(
+                        server_id.0,
+                        &server_binary.path,
+                        &server_binary.arguments,
+                        &root_path,
+                        cx,
+                    )?;
+                    Ok(server)
+                });
+
+                (server_id, task)
+            })
+            .collect()
     }
 
     pub fn language_server_binary_statuses(
         &self,
-    ) -> async_broadcast::Receiver<(Arc<Language>, LanguageServerBinaryStatus)> {
+    ) -> async_broadcast::Receiver<(LanguageServerId, LanguageServerBinaryStatus)> {
         self.lsp_binary_statuses_rx.clone()
     }
 }
@@ -539,12 +738,20 @@ impl Default for LanguageRegistry {
 }
 
 async fn get_server_binary_path(
+    registry: Arc<LanguageRegistry>,
+    server_id: LanguageServerId,
     adapter: Arc<CachedLspAdapter>,
-    language: Arc<Language>,
+    delegate: Arc<dyn LspAdapterDelegate>,
     http_client: Arc<dyn HttpClient>,
     download_dir: Arc<Path>,
-    statuses: async_broadcast::Sender<(Arc<Language>, LanguageServerBinaryStatus)>,
-) -> Result<PathBuf> {
+) -> Result<LanguageServerBinary> {
+    if let Some(binary) = adapter.check_if_user_installed(delegate.as_ref()).await {
+        registry
+            .set_binary_status(server_id, LanguageServerBinaryStatus::Found)
+            .await?;
+        return Ok(binary);
+    }
+
     let container_dir = download_dir.join(adapter.name.0.as_ref());
     if !container_dir.exists() {
         smol::fs::create_dir_all(&container_dir)
@@ -552,61 +759,58 @@ async fn get_server_binary_path(
             .context("failed to create container directory")?;
     }
 
-    let path = fetch_latest_server_binary_path(
+    let binary = fetch_latest_server_binary(
+        registry.clone(),
+        server_id,
         adapter.clone(),
-        language.clone(),
         http_client,
         &container_dir,
-        statuses.clone(),
     )
     .await;
-    if let Err(error) = path.as_ref() {
-        if let Some(cached_path) = adapter.cached_server_binary(container_dir).await {
-            statuses
-                .broadcast((language.clone(), LanguageServerBinaryStatus::Cached))
+    if let Err(error) = binary.as_ref() {
+        if let Some(cached_binary) = adapter.cached_server_binary(container_dir).await {
+            registry
+                .set_binary_status(server_id, LanguageServerBinaryStatus::Cached)
                 .await?;
-            return Ok(cached_path);
+            return Ok(cached_binary);
         } else {
-            statuses
-                .broadcast((
-                    language.clone(),
+            registry
+                .set_binary_status(
+                    server_id,
                     LanguageServerBinaryStatus::Failed {
                         error: format!("{:?}", error),
                     },
-                ))
+                )
                 .await?;
         }
     }
-    path
+    binary
 }
 
-async fn fetch_latest_server_binary_path(
+async fn fetch_latest_server_binary(
+    registry: Arc<LanguageRegistry>,
+    server_id: LanguageServerId,
     adapter: Arc<CachedLspAdapter>,
-    language: Arc<Language>,
     http_client: Arc<dyn HttpClient>,
     container_dir: &Path,
-    lsp_binary_statuses_tx: async_broadcast::Sender<(Arc<Language>, LanguageServerBinaryStatus)>,
-) -> Result<PathBuf> {
+) -> Result<LanguageServerBinary> {
     let container_dir: Arc<Path> = container_dir.into();
-    lsp_binary_statuses_tx
-        .broadcast((
-            language.clone(),
-            LanguageServerBinaryStatus::CheckingForUpdate,
-        ))
+    registry
+        .set_binary_status(server_id, LanguageServerBinaryStatus::CheckingForUpdate)
         .await?;
     let version_info = adapter
         .fetch_latest_server_version(http_client.clone())
         .await?;
-    lsp_binary_statuses_tx
-        .broadcast((language.clone(), LanguageServerBinaryStatus::Downloading))
+    registry
+        .set_binary_status(server_id, LanguageServerBinaryStatus::Downloading)
         .await?;
-    let path = adapter
+    let binary = adapter
         .fetch_server_binary(version_info, http_client, container_dir.to_path_buf())
         .await?;
-    lsp_binary_statuses_tx
-        .broadcast((language.clone(), LanguageServerBinaryStatus::Downloaded))
+    registry
+        .set_binary_status(server_id, LanguageServerBinaryStatus::Downloaded)
         .await?;
-    Ok(path)
+    Ok(binary)
 }
 
 impl Language {
@@ -625,15 +829,22 @@ impl Language {
                     highlight_map: Default::default(),
                 })
             }),
-            adapter: None,
+            adapters: Vec::new(),
 
             #[cfg(any(test, feature = "test-support"))]
             fake_adapter: None,
         }
     }
 
+    /// The primary (first) adapter configured for this language, if any.
     pub fn lsp_adapter(&self) -> Option<Arc<CachedLspAdapter>> {
-        self.adapter.clone()
+        self.adapters.first().cloned()
+    }
+
+    /// Every adapter configured for this language -- e.g. a primary LSP server alongside a
+    /// dedicated linter or formatter server.
+    pub fn lsp_adapters(&self) -> &[Arc<CachedLspAdapter>] {
+        &self.adapters
     }
 
     pub fn with_highlights_query(mut self, source: &str) -> Result<Self> {
@@ -753,7 +964,17 @@ impl Language {
     }
 
     pub fn with_lsp_adapter(mut self, lsp_adapter: Arc<CachedLspAdapter>) -> Self {
-        self.adapter = Some(lsp_adapter);
+        self.adapters = vec![lsp_adapter];
+        self
+    }
+
+    /// Like `with_lsp_adapter`, but configures multiple adapters at once -- e.g. a primary LSP
+    /// server paired with a dedicated linter or formatter server.
+    pub fn with_lsp_adapters(
+        mut self,
+        lsp_adapters: impl IntoIterator<Item = Arc<CachedLspAdapter>>,
+    ) -> Self {
+        self.adapters = lsp_adapters.into_iter().collect();
         self
     }
 
@@ -765,7 +986,7 @@ impl Language {
         let (servers_tx, servers_rx) = mpsc::unbounded();
         self.fake_adapter = Some((servers_tx, fake_lsp_adapter.clone()));
         let adapter = CachedLspAdapter::new(fake_lsp_adapter).await;
-        self.adapter = Some(adapter);
+        self.adapters = vec![adapter];
         servers_rx
     }
 
@@ -784,35 +1005,45 @@ impl Language {
             .map(|(start, end)| (start, end))
     }
 
-    pub async fn disk_based_diagnostic_sources(&self) -> &[String] {
-        match self.adapter.as_ref() {
-            Some(adapter) => &adapter.disk_based_diagnostic_sources,
-            None => &[],
-        }
+    pub async fn disk_based_diagnostic_sources(&self) -> Vec<&str> {
+        self.adapters
+            .iter()
+            .flat_map(|adapter| {
+                adapter
+                    .disk_based_diagnostic_sources
+                    .iter()
+                    .map(String::as_str)
+            })
+            .collect()
     }
 
     pub async fn disk_based_diagnostics_progress_token(&self) -> Option<&str> {
-        if let Some(adapter) = self.adapter.as_ref() {
-            adapter.disk_based_diagnostics_progress_token.as_deref()
-        } else {
-            None
-        }
+        self.adapters
+            .iter()
+            .find_map(|adapter| adapter.disk_based_diagnostics_progress_token.as_deref())
     }
 
+    /// Runs `diagnostics` through every adapter's processor in turn, so e.g. a dedicated linter
+    /// adapter can rewrite its own diagnostics the same way the primary LSP server's adapter does.
     pub async fn process_diagnostics(&self, diagnostics: &mut lsp::PublishDiagnosticsParams) {
-        if let Some(processor) = self.adapter.as_ref() {
-            processor.process_diagnostics(diagnostics).await;
+        for adapter in &self.adapters {
+            adapter.process_diagnostics(diagnostics).await;
         }
     }
 
+    /// Tries each adapter in turn, returning the first label an adapter produces -- this lets a
+    /// secondary adapter (e.g. a linter with no completion support of its own) fall through to the
+    /// primary server's labeling.
     pub async fn label_for_completion(
         self: &Arc<Self>,
         completion: &lsp::CompletionItem,
     ) -> Option<CodeLabel> {
-        self.adapter
-            .as_ref()?
-            .label_for_completion(completion, self)
-            .await
+        for adapter in &self.adapters {
+            if let Some(label) = adapter.label_for_completion(completion, self).await {
+                return Some(label);
+            }
+        }
+        None
     }
 
     pub async fn label_for_symbol(
@@ -820,36 +1051,115 @@ impl Language {
         name: &str,
         kind: lsp::SymbolKind,
     ) -> Option<CodeLabel> {
-        self.adapter
-            .as_ref()?
-            .label_for_symbol(name, kind, self)
-            .await
+        for adapter in &self.adapters {
+            if let Some(label) = adapter.label_for_symbol(name, kind, self).await {
+                return Some(label);
+            }
+        }
+        None
+    }
+
+    /// Merges `list.item_defaults` into each of `list.items` that's missing the corresponding
+    /// field, so `label_for_completion` doesn't have to know about list-level defaults -- servers
+    /// that rely on `itemDefaults` (rather than repeating `insertTextFormat`/`insertTextMode`/
+    /// `commitCharacters`/`data`/`editRange` on every item) would otherwise show up as completions
+    /// with no text edit and no commit characters.
+    pub fn process_completions(&self, list: &mut lsp::CompletionList) {
+        let defaults = match &list.item_defaults {
+            Some(defaults) => defaults,
+            None => return,
+        };
+        for item in &mut list.items {
+            resolve_completion_item_defaults(item, defaults);
+        }
     }
 
     pub fn highlight_text<'a>(
         self: &'a Arc<Self>,
         text: &'a Rope,
         range: Range<usize>,
+    ) -> Vec<(Range<usize>, HighlightId)> {
+        self.highlight_text_with_injections(text, range, &|_| None)
+    }
+
+    /// Like `highlight_text`, but also highlights injected regions -- e.g. the JS in an HTML
+    /// `<script>` tag, a fenced code block in Markdown, or SQL embedded in a string literal --
+    /// instead of leaving them as plain text. `resolve_injected_language` maps an injection's
+    /// language name (from the grammar's injection query, see `with_injection_query`) to the
+    /// `Language` that should highlight it; callers that don't have another `Language` handy to
+    /// resolve against (e.g. a registry) can pass `&|_| None` to fall back to today's behavior,
+    /// which is exactly what `highlight_text` does above.
+    ///
+    /// Each injected range is highlighted independently by recursing into the injected language's
+    /// own grammar, then spliced into the outer result, overriding whatever (if anything) the
+    /// outer grammar highlighted for that same byte range.
+    pub fn highlight_text_with_injections<'a>(
+        self: &'a Arc<Self>,
+        text: &'a Rope,
+        range: Range<usize>,
+        resolve_injected_language: &dyn Fn(&str) -> Option<Arc<Language>>,
     ) -> Vec<(Range<usize>, HighlightId)> {
         let mut result = Vec::new();
-        if let Some(grammar) = &self.grammar {
-            let tree = grammar.parse_text(text, None);
-            let captures =
-                SyntaxSnapshot::single_tree_captures(range.clone(), text, &tree, self, |grammar| {
-                    grammar.highlights_query.as_ref()
-                });
-            let highlight_maps = vec![grammar.highlight_map()];
-            let mut offset = 0;
-            for chunk in BufferChunks::new(text, range, Some((captures, highlight_maps)), vec![]) {
-                let end_offset = offset + chunk.text.len();
-                if let Some(highlight_id) = chunk.syntax_highlight_id {
-                    if !highlight_id.is_default() {
-                        result.push((offset..end_offset, highlight_id));
-                    }
+        let grammar = match &self.grammar {
+            Some(grammar) => grammar,
+            None => return result,
+        };
+
+        let tree = grammar.parse_text(text, None);
+        let captures =
+            SyntaxSnapshot::single_tree_captures(range.clone(), text, &tree, self, |grammar| {
+                grammar.highlights_query.as_ref()
+            });
+        let highlight_maps = vec![grammar.highlight_map()];
+        let mut offset = 0;
+        for chunk in BufferChunks::new(text, range.clone(), Some((captures, highlight_maps)), vec![])
+        {
+            let end_offset = offset + chunk.text.len();
+            if let Some(highlight_id) = chunk.syntax_highlight_id {
+                if !highlight_id.is_default() {
+                    result.push((offset..end_offset, highlight_id));
+                }
+            }
+            offset = end_offset;
+        }
+
+        if grammar.injection_config.is_some() {
+            let source = text.to_string().into_bytes();
+            for (injection_range, language_name) in grammar.injection_ranges(&source, &tree) {
+                let injected_start = injection_range.start.max(range.start);
+                let injected_end = injection_range.end.min(range.end);
+                if injected_start >= injected_end {
+                    continue;
+                }
+                let injected_language = match resolve_injected_language(&language_name) {
+                    Some(language) => language,
+                    None => continue,
+                };
+                let injected_highlights = injected_language.highlight_text_with_injections(
+                    text,
+                    injected_start..injected_end,
+                    resolve_injected_language,
+                );
+                if injected_highlights.is_empty() {
+                    continue;
                 }
-                offset = end_offset;
+                // `injected_highlights` is 0-based relative to `injected_start`, but `result` is
+                // 0-based relative to `range.start` -- shift the injected runs (and the retain
+                // bounds used to carve out the space for them) into that same frame before
+                // splicing them in.
+                let retain_start = injected_start - range.start;
+                let retain_end = injected_end - range.start;
+                result.retain(|(existing_range, _)| {
+                    existing_range.end <= retain_start || existing_range.start >= retain_end
+                });
+                result.extend(injected_highlights.into_iter().map(|(highlight_range, id)| {
+                    let shift = injected_start - range.start;
+                    (highlight_range.start + shift..highlight_range.end + shift, id)
+                }));
             }
+            result.sort_by_key(|(range, _)| range.start);
         }
+
         result
     }
 
@@ -922,6 +1232,49 @@ impl Grammar {
             .capture_index_for_name(name)?;
         Some(self.highlight_map.lock().get(capture_id))
     }
+
+    /// Runs the injection query built by `with_injection_query` against an already-parsed `tree`
+    /// and returns, for each match, the byte range of its `@content` capture together with the
+    /// name of the language that should highlight that range -- from the match's `@language`
+    /// capture if the query captures one, otherwise from the `(#set! language "...")` property
+    /// associated with whichever pattern matched.
+    fn injection_ranges(&self, source: &[u8], tree: &Tree) -> Vec<(Range<usize>, String)> {
+        let injection_config = match &self.injection_config {
+            Some(injection_config) => injection_config,
+            None => return Vec::new(),
+        };
+
+        let mut cursor = QueryCursor::new();
+        cursor
+            .matches(&injection_config.query, tree.root_node(), source)
+            .filter_map(|mat| {
+                let content_range = mat
+                    .captures
+                    .iter()
+                    .find(|capture| capture.index == injection_config.content_capture_ix)?
+                    .node
+                    .byte_range();
+                let language_name = injection_config
+                    .language_capture_ix
+                    .and_then(|capture_ix| {
+                        mat.captures
+                            .iter()
+                            .find(|capture| capture.index == capture_ix)
+                            .and_then(|capture| capture.node.utf8_text(source).ok())
+                            .map(str::to_string)
+                    })
+                    .or_else(|| {
+                        injection_config
+                            .languages_by_pattern_ix
+                            .get(mat.pattern_index)
+                            .cloned()
+                            .flatten()
+                            .map(|name| name.to_string())
+                    })?;
+                Some((content_range, language_name))
+            })
+            .collect()
+    }
 }
 
 impl CodeLabel {
@@ -972,11 +1325,11 @@ impl LspAdapter for Arc<FakeLspAdapter> {
         _: Box<dyn 'static + Send + Any>,
         _: Arc<dyn HttpClient>,
         _: PathBuf,
-    ) -> Result<PathBuf> {
+    ) -> Result<LanguageServerBinary> {
         unreachable!();
     }
 
-    async fn cached_server_binary(&self, _: PathBuf) -> Option<PathBuf> {
+    async fn cached_server_binary(&self, _: PathBuf) -> Option<LanguageServerBinary> {
         unreachable!();
     }
 
@@ -1025,3 +1378,50 @@ pub fn range_from_lsp(range: lsp::Range) -> Range<PointUtf16> {
     }
     start..end
 }
+
+/// Fills in `item`'s fields from `defaults` wherever `item` doesn't already specify its own --
+/// mirrors the fallback behavior the LSP spec describes for `CompletionList.itemDefaults`. Only
+/// `edit_range` is synthesized into a `text_edit` rather than copied directly, since a default
+/// range has no `new_text` of its own; `insert_text` (falling back to the item's `label`, per
+/// spec) supplies it.
+fn resolve_completion_item_defaults(
+    item: &mut lsp::CompletionItem,
+    defaults: &lsp::CompletionListItemDefaults,
+) {
+    if item.insert_text_format.is_none() {
+        item.insert_text_format = defaults.insert_text_format;
+    }
+    if item.insert_text_mode.is_none() {
+        item.insert_text_mode = defaults.insert_text_mode;
+    }
+    if item.commit_characters.is_none() {
+        item.commit_characters = defaults.commit_characters.clone();
+    }
+    if item.data.is_none() {
+        item.data = defaults.data.clone();
+    }
+    if item.text_edit.is_none() {
+        if let Some(edit_range) = &defaults.edit_range {
+            let new_text = item
+                .insert_text
+                .clone()
+                .unwrap_or_else(|| item.label.clone());
+            item.text_edit = Some(match edit_range {
+                lsp::CompletionListItemDefaultsEditRange::Range(range) => {
+                    lsp::CompletionTextEdit::Edit(lsp::TextEdit {
+                        range: *range,
+                        new_text,
+                    })
+                }
+                lsp::CompletionListItemDefaultsEditRange::RangeWithInsertReplace {
+                    insert,
+                    replace,
+                } => lsp::CompletionTextEdit::InsertAndReplace(lsp::InsertReplaceEdit {
+                    new_text,
+                    insert: *insert,
+                    replace: *replace,
+                }),
+            });
+        }
+    }
+}
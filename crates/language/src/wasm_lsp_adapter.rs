@@ -0,0 +1,442 @@
+use crate::{CodeLabel, Language, LanguageServerBinary, LanguageServerName, LspAdapter};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use client::http::HttpClient;
+use lsp::SymbolKind;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use util::ResultExt;
+use wasmtime::{Caller, Engine, Instance, Linker, Memory, Store};
+
+/// The wire format a guest's `fetch_server_binary`/`cached_server_binary` exports serialize as
+/// JSON into their returned bytes; mirrors [`LanguageServerBinary`], which isn't `Deserialize`
+/// itself since nothing else in this crate needs to parse one back out of bytes.
+#[derive(Deserialize)]
+struct WireLanguageServerBinary {
+    path: PathBuf,
+    #[serde(default)]
+    arguments: Vec<String>,
+}
+
+impl From<WireLanguageServerBinary> for LanguageServerBinary {
+    fn from(wire: WireLanguageServerBinary) -> Self {
+        Self {
+            path: wire.path,
+            arguments: wire.arguments,
+        }
+    }
+}
+
+/// The wire format a guest's `label_for_completion`/`label_for_symbol` exports serialize as JSON:
+/// mirrors [`CodeLabel`], except each run names its highlight by the same capture name (e.g.
+/// `"keyword"`, `"variable"`) the language's highlights query would use, since a `HighlightId` is
+/// a host-side index the guest has no way to produce itself. `code_label_from_wire` resolves those
+/// names back to `HighlightId`s via `Grammar::highlight_id_for_name`.
+#[derive(Deserialize)]
+struct WireCodeLabel {
+    text: String,
+    filter_range: Range<usize>,
+    runs: Vec<WireHighlightRun>,
+}
+
+#[derive(Deserialize)]
+struct WireHighlightRun {
+    range: Range<usize>,
+    highlight_name: String,
+}
+
+/// Resolves a [`WireCodeLabel`]'s capture names into real `HighlightId`s using `language`'s
+/// grammar. A run whose name isn't recognized by this language's highlights query is dropped
+/// rather than failing the whole label, since an extension may reference capture names this
+/// particular language doesn't define.
+fn code_label_from_wire(wire: WireCodeLabel, language: &Arc<Language>) -> CodeLabel {
+    let grammar = language.grammar();
+    let runs = wire
+        .runs
+        .into_iter()
+        .filter_map(|run| {
+            let highlight_id = grammar?.highlight_id_for_name(&run.highlight_name)?;
+            Some((run.range, highlight_id))
+        })
+        .collect();
+    CodeLabel {
+        text: wire.text,
+        filter_range: wire.filter_range,
+        runs,
+    }
+}
+
+/// Implements [`LspAdapter`] by calling into a `wasm32-wasi` module loaded at runtime, so a
+/// language server integration can be shipped as a downloadable extension instead of a Rust type
+/// compiled into this crate. Everything the guest can reach is explicitly exposed by a host
+/// function below -- there's no ambient filesystem or network access.
+pub struct WasmLspAdapter {
+    name: LanguageServerName,
+    runtime: Arc<WasmRuntime>,
+}
+
+/// Per-module state reachable from host functions the guest imports. The guest gets
+/// `container_dir` only as a path string from `host_container_dir`; no filesystem host functions
+/// are linked, so it can't read or write files there or anywhere else. Network access goes
+/// through `http` rather than a real socket, since `wasm32-wasi` guests have none of their own.
+struct WasmHostState {
+    http: Arc<dyn HttpClient>,
+    container_dir: PathBuf,
+}
+
+struct WasmRuntime {
+    instance: Instance,
+    store: Mutex<Store<WasmHostState>>,
+}
+
+impl WasmLspAdapter {
+    /// Compiles and instantiates `wasm_bytes` as a `wasm32-wasi` module implementing this host's
+    /// LSP-adapter ABI (see the `call_*` methods on [`WasmRuntime`] for the exact guest exports
+    /// expected).
+    pub fn load(
+        name: LanguageServerName,
+        wasm_bytes: &[u8],
+        http: Arc<dyn HttpClient>,
+        container_dir: PathBuf,
+    ) -> Result<Self> {
+        let engine = Engine::default();
+        let module = wasmtime::Module::from_binary(&engine, wasm_bytes)
+            .context("failed to compile wasm LSP adapter module")?;
+
+        let mut linker = Linker::new(&engine);
+        link_host_functions(&mut linker).context("failed to link wasm LSP adapter host imports")?;
+
+        let mut store = Store::new(
+            &engine,
+            WasmHostState {
+                http,
+                container_dir,
+            },
+        );
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .context("failed to instantiate wasm LSP adapter module")?;
+
+        Ok(Self {
+            name,
+            runtime: Arc::new(WasmRuntime {
+                instance,
+                store: Mutex::new(store),
+            }),
+        })
+    }
+
+    /// Like [`Self::load`], but reads the compiled module from `wasm_path` on disk -- the form a
+    /// downloaded language server extension actually takes, rather than bytes already resident in
+    /// memory.
+    pub fn load_from_file(
+        name: LanguageServerName,
+        wasm_path: &Path,
+        http: Arc<dyn HttpClient>,
+        container_dir: PathBuf,
+    ) -> Result<Self> {
+        let wasm_bytes = std::fs::read(wasm_path)
+            .with_context(|| format!("failed to read wasm LSP adapter module at {:?}", wasm_path))?;
+        Self::load(name, &wasm_bytes, http, container_dir)
+    }
+}
+
+#[async_trait]
+impl LspAdapter for WasmLspAdapter {
+    async fn name(&self) -> LanguageServerName {
+        self.name.clone()
+    }
+
+    async fn fetch_latest_server_version(
+        &self,
+        http: Arc<dyn HttpClient>,
+    ) -> Result<Box<dyn 'static + Send + Any>> {
+        let runtime = self.runtime.clone();
+        // `Any` can't cross the wasm boundary, so the guest hands back an opaque byte blob here
+        // (typically a small JSON document) instead of a real version type; `fetch_server_binary`
+        // below gets that same blob back and is the only thing that needs to understand it.
+        let version_blob =
+            smol::unblock(move || runtime.call_fetch_latest_server_version(http)).await?;
+        Ok(Box::new(version_blob))
+    }
+
+    async fn fetch_server_binary(
+        &self,
+        version: Box<dyn 'static + Send + Any>,
+        http: Arc<dyn HttpClient>,
+        container_dir: PathBuf,
+    ) -> Result<LanguageServerBinary> {
+        let version_blob = *version.downcast::<Vec<u8>>().map_err(|_| {
+            anyhow!("wasm LSP adapter version blob was not produced by this adapter")
+        })?;
+        let runtime = self.runtime.clone();
+        smol::unblock(move || runtime.call_fetch_server_binary(version_blob, http, container_dir))
+            .await
+    }
+
+    async fn cached_server_binary(&self, container_dir: PathBuf) -> Option<LanguageServerBinary> {
+        let runtime = self.runtime.clone();
+        smol::unblock(move || runtime.call_cached_server_binary(container_dir)).await
+    }
+
+    async fn label_for_completion(
+        &self,
+        completion_item: &lsp::CompletionItem,
+        language: &Arc<Language>,
+    ) -> Option<CodeLabel> {
+        let runtime = self.runtime.clone();
+        let completion_item = serde_json::to_vec(completion_item).log_err()?;
+        let wire =
+            smol::unblock(move || runtime.call_label_for_completion(completion_item)).await?;
+        Some(code_label_from_wire(wire, language))
+    }
+
+    async fn label_for_symbol(
+        &self,
+        name: &str,
+        kind: SymbolKind,
+        language: &Arc<Language>,
+    ) -> Option<CodeLabel> {
+        let runtime = self.runtime.clone();
+        let request = serde_json::to_vec(&WireSymbolLabelRequest {
+            name: name.to_string(),
+            kind,
+        })
+        .log_err()?;
+        let wire = smol::unblock(move || runtime.call_label_for_symbol(request)).await?;
+        Some(code_label_from_wire(wire, language))
+    }
+}
+
+/// The JSON request body for the guest's `label_for_symbol` export.
+#[derive(Serialize)]
+struct WireSymbolLabelRequest {
+    name: String,
+    kind: SymbolKind,
+}
+
+impl WasmRuntime {
+    /// Guest calls are synchronous once entered -- wasm has no async story of its own -- so every
+    /// `call_*` method here is a plain blocking function; callers are expected to run them via
+    /// `smol::unblock` so a slow guest (e.g. one doing its own HTTP round trip through
+    /// `host_http_get`) doesn't stall the async executor's thread.
+    fn call_fetch_latest_server_version(&self, http: Arc<dyn HttpClient>) -> Result<Vec<u8>> {
+        let mut store = self.store.lock();
+        store.data_mut().http = http;
+        let call = self
+            .instance
+            .get_typed_func::<(), (i32, i32)>(&mut *store, "fetch_latest_server_version")
+            .context("wasm LSP adapter is missing a `fetch_latest_server_version` export")?;
+        let (ptr, len) = call
+            .call(&mut *store, ())
+            .context("wasm LSP adapter's fetch_latest_server_version trapped")?;
+        read_guest_bytes(&self.instance, &mut store, ptr, len)
+    }
+
+    fn call_fetch_server_binary(
+        &self,
+        version_blob: Vec<u8>,
+        http: Arc<dyn HttpClient>,
+        container_dir: PathBuf,
+    ) -> Result<LanguageServerBinary> {
+        let mut store = self.store.lock();
+        store.data_mut().http = http;
+        store.data_mut().container_dir = container_dir;
+
+        let (version_ptr, version_len) =
+            write_guest_bytes(&self.instance, &mut store, &version_blob)?;
+        let call = self
+            .instance
+            .get_typed_func::<(i32, i32), (i32, i32)>(&mut *store, "fetch_server_binary")
+            .context("wasm LSP adapter is missing a `fetch_server_binary` export")?;
+        let (binary_ptr, binary_len) = call
+            .call(&mut *store, (version_ptr, version_len))
+            .context("wasm LSP adapter's fetch_server_binary trapped")?;
+        let binary_bytes = read_guest_bytes(&self.instance, &mut store, binary_ptr, binary_len)?;
+        let wire: WireLanguageServerBinary = serde_json::from_slice(&binary_bytes)
+            .context("wasm LSP adapter's fetch_server_binary returned malformed JSON")?;
+        Ok(wire.into())
+    }
+
+    fn call_cached_server_binary(&self, container_dir: PathBuf) -> Option<LanguageServerBinary> {
+        let mut store = self.store.lock();
+        store.data_mut().container_dir = container_dir;
+        let call = self
+            .instance
+            .get_typed_func::<(), (i32, i32)>(&mut *store, "cached_server_binary")
+            .ok()?;
+        let (ptr, len) = call.call(&mut *store, ()).ok()?;
+        if len == 0 {
+            return None;
+        }
+        let binary_bytes = read_guest_bytes(&self.instance, &mut store, ptr, len).ok()?;
+        let wire: WireLanguageServerBinary = serde_json::from_slice(&binary_bytes).ok()?;
+        Some(wire.into())
+    }
+
+    /// `label_for_completion`/`label_for_symbol` are optional guest exports -- an extension that
+    /// only implements install/run still works, it just gets plain, unstyled labels -- so these
+    /// two return `None` on any failure instead of propagating an error like the install/run
+    /// `call_*` methods above.
+    fn call_label_for_completion(&self, completion_item: Vec<u8>) -> Option<WireCodeLabel> {
+        let mut store = self.store.lock();
+        let (ptr, len) = write_guest_bytes(&self.instance, &mut store, &completion_item).ok()?;
+        let call = self
+            .instance
+            .get_typed_func::<(i32, i32), (i32, i32)>(&mut *store, "label_for_completion")
+            .ok()?;
+        let (label_ptr, label_len) = call.call(&mut *store, (ptr, len)).ok()?;
+        if label_len == 0 {
+            return None;
+        }
+        let label_bytes = read_guest_bytes(&self.instance, &mut store, label_ptr, label_len).ok()?;
+        serde_json::from_slice(&label_bytes).ok()
+    }
+
+    fn call_label_for_symbol(&self, request: Vec<u8>) -> Option<WireCodeLabel> {
+        let mut store = self.store.lock();
+        let (ptr, len) = write_guest_bytes(&self.instance, &mut store, &request).ok()?;
+        let call = self
+            .instance
+            .get_typed_func::<(i32, i32), (i32, i32)>(&mut *store, "label_for_symbol")
+            .ok()?;
+        let (label_ptr, label_len) = call.call(&mut *store, (ptr, len)).ok()?;
+        if label_len == 0 {
+            return None;
+        }
+        let label_bytes = read_guest_bytes(&self.instance, &mut store, label_ptr, label_len).ok()?;
+        serde_json::from_slice(&label_bytes).ok()
+    }
+}
+
+/// Registers the host functions guest modules can import: network access via `http` (since
+/// `wasm32-wasi` has no sockets of its own) and the `container_dir` path as a string. Nothing else
+/// is linked -- no WASI imports and no filesystem functions -- so the guest has no way to read or
+/// write files, inside `container_dir` or anywhere else.
+fn link_host_functions(linker: &mut Linker<WasmHostState>) -> Result<()> {
+    linker.func_wrap(
+        "zed",
+        "host_http_get",
+        |mut caller: Caller<'_, WasmHostState>, url_ptr: i32, url_len: i32| -> (i32, i32) {
+            let Ok(url_bytes) = read_guest_bytes_from_caller(&mut caller, url_ptr, url_len) else {
+                return (0, 0);
+            };
+            let url = String::from_utf8_lossy(&url_bytes).into_owned();
+
+            let http = caller.data().http.clone();
+            let body = smol::block_on(async move {
+                let mut response = http.get(&url, Default::default(), true).await.ok()?;
+                let mut body = Vec::new();
+                futures::AsyncReadExt::read_to_end(response.body_mut(), &mut body)
+                    .await
+                    .ok()?;
+                Some(body)
+            });
+
+            match body {
+                Some(body) => write_guest_bytes_from_caller(&mut caller, &body).unwrap_or((0, 0)),
+                None => (0, 0),
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "zed",
+        "host_container_dir",
+        |mut caller: Caller<'_, WasmHostState>| -> (i32, i32) {
+            let container_dir = caller
+                .data()
+                .container_dir
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes();
+            write_guest_bytes_from_caller(&mut caller, &container_dir).unwrap_or((0, 0))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Copies `bytes` into guest memory by calling the guest's own exported `alloc`, returning the
+/// `(ptr, len)` pair the guest's own exports expect back from a host call.
+fn write_guest_bytes(
+    instance: &Instance,
+    store: &mut Store<WasmHostState>,
+    bytes: &[u8],
+) -> Result<(i32, i32)> {
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&mut *store, "alloc")
+        .context("wasm LSP adapter is missing an `alloc` export")?;
+    let ptr = alloc
+        .call(&mut *store, bytes.len() as i32)
+        .context("wasm LSP adapter's alloc trapped")?;
+    let memory = instance
+        .get_memory(&mut *store, "memory")
+        .context("wasm LSP adapter is missing a `memory` export")?;
+    memory
+        .write(&mut *store, ptr as usize, bytes)
+        .context("failed to write into wasm LSP adapter memory")?;
+    Ok((ptr, bytes.len() as i32))
+}
+
+fn read_guest_bytes(
+    instance: &Instance,
+    store: &mut Store<WasmHostState>,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>> {
+    let memory = instance
+        .get_memory(&mut *store, "memory")
+        .context("wasm LSP adapter is missing a `memory` export")?;
+    let mut bytes = vec![0; len as usize];
+    memory
+        .read(&mut *store, ptr as usize, &mut bytes)
+        .context("failed to read from wasm LSP adapter memory")?;
+    Ok(bytes)
+}
+
+fn memory_from_caller(caller: &mut Caller<'_, WasmHostState>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .context("wasm LSP adapter is missing a `memory` export")
+}
+
+fn read_guest_bytes_from_caller(
+    caller: &mut Caller<'_, WasmHostState>,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>> {
+    let memory = memory_from_caller(caller)?;
+    let mut bytes = vec![0; len as usize];
+    memory
+        .read(&mut *caller, ptr as usize, &mut bytes)
+        .context("failed to read from wasm LSP adapter memory")?;
+    Ok(bytes)
+}
+
+fn write_guest_bytes_from_caller(
+    caller: &mut Caller<'_, WasmHostState>,
+    bytes: &[u8],
+) -> Result<(i32, i32)> {
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(|export| export.into_func())
+        .context("wasm LSP adapter is missing an `alloc` export")?
+        .typed::<i32, i32>(&caller)
+        .context("wasm LSP adapter's `alloc` export has an unexpected signature")?;
+    let ptr = alloc
+        .call(&mut *caller, bytes.len() as i32)
+        .context("wasm LSP adapter's alloc trapped")?;
+    let memory = memory_from_caller(caller)?;
+    memory
+        .write(&mut *caller, ptr as usize, bytes)
+        .context("failed to write into wasm LSP adapter memory")?;
+    Ok((ptr, bytes.len() as i32))
+}
@@ -1,10 +1,45 @@
+use anyhow::Result;
+use futures::{channel::mpsc, future::BoxFuture};
+use std::sync::Arc;
+
 pub mod prod;
 
 #[cfg(not(any(test, feature = "test-support")))]
 pub use prod::*;
 
+#[cfg(not(any(test, feature = "test-support")))]
+pub mod video_view;
+
 #[cfg(any(test, feature = "test-support"))]
 mod test;
 
 #[cfg(any(test, feature = "test-support"))]
 pub use test::*;
+
+/// The room-management surface that both the FFI-backed `prod` implementation and the in-process
+/// `test` implementation provide. Each backend has its own concrete track/publication/display
+/// types (the `prod` ones wrap native LiveKit pointers; the `test` ones don't), so those vary by
+/// associated type -- but the operations you can perform with them, and the shape of a connection,
+/// are shared. This lets code that only needs to drive a room (not construct one) be written once
+/// and exercised against either backend, e.g. in a unit test that never touches the real LiveKit
+/// framework.
+pub trait RoomBackend: Send + Sync + 'static {
+    type LocalVideoTrack;
+    type LocalTrackPublication;
+    type RemoteVideoTrack;
+    type RemoteVideoTrackUpdate;
+    type MacOSDisplay;
+
+    fn connect(self: &Arc<Self>, url: &str, token: &str) -> BoxFuture<'static, Result<()>>;
+    fn disconnect(&self);
+    fn display_sources(
+        self: &Arc<Self>,
+    ) -> BoxFuture<'static, Result<Vec<Self::MacOSDisplay>>>;
+    fn publish_video_track(
+        self: &Arc<Self>,
+        track: &Self::LocalVideoTrack,
+    ) -> BoxFuture<'static, Result<Self::LocalTrackPublication>>;
+    fn unpublish_track(&self, publication: Self::LocalTrackPublication);
+    fn remote_video_tracks(&self, participant_id: &str) -> Vec<Arc<Self::RemoteVideoTrack>>;
+    fn remote_video_track_updates(&self) -> mpsc::UnboundedReceiver<Self::RemoteVideoTrackUpdate>;
+}
@@ -1,3 +1,4 @@
+use crate::RoomBackend;
 use anyhow::{anyhow, Context, Result};
 use core_foundation::{
     array::{CFArray, CFArrayRef},
@@ -6,6 +7,7 @@ use core_foundation::{
 };
 use futures::{
     channel::{mpsc, oneshot},
+    future::BoxFuture,
     Future,
 };
 pub use media::core_video::CVImageBuffer;
@@ -14,13 +16,16 @@ use parking_lot::Mutex;
 use postage::watch;
 use std::{
     ffi::c_void,
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicU32, Ordering::SeqCst},
+        Arc, Weak,
+    },
 };
 
 extern "C" {
     fn LKRoomDelegateCreate(
         callback_data: *mut c_void,
-        on_did_disconnect: extern "C" fn(callback_data: *mut c_void),
+        on_did_disconnect: extern "C" fn(callback_data: *mut c_void, reason: CFStringRef),
         on_did_subscribe_to_remote_video_track: extern "C" fn(
             callback_data: *mut c_void,
             publisher_id: CFStringRef,
@@ -32,6 +37,17 @@ extern "C" {
             publisher_id: CFStringRef,
             track_id: CFStringRef,
         ),
+        on_did_subscribe_to_remote_audio_track: extern "C" fn(
+            callback_data: *mut c_void,
+            publisher_id: CFStringRef,
+            track_id: CFStringRef,
+            remote_track: *const c_void,
+        ),
+        on_did_unsubscribe_from_remote_audio_track: extern "C" fn(
+            callback_data: *mut c_void,
+            publisher_id: CFStringRef,
+            track_id: CFStringRef,
+        ),
     ) -> *const c_void;
 
     fn LKRoomCreate(delegate: *const c_void) -> *const c_void;
@@ -49,6 +65,12 @@ extern "C" {
         callback: extern "C" fn(*mut c_void, *mut c_void, CFStringRef),
         callback_data: *mut c_void,
     );
+    fn LKRoomPublishAudioTrack(
+        room: *const c_void,
+        track: *const c_void,
+        callback: extern "C" fn(*mut c_void, *mut c_void, CFStringRef),
+        callback_data: *mut c_void,
+    );
     fn LKRoomUnpublishTrack(room: *const c_void, publication: *const c_void);
     fn LKRoomVideoTracksForRemoteParticipant(
         room: *const c_void,
@@ -73,14 +95,46 @@ extern "C" {
         ),
     );
     fn LKCreateScreenShareTrackForDisplay(display: *const c_void) -> *const c_void;
+    fn LKWindowSources(
+        callback_data: *mut c_void,
+        callback: extern "C" fn(
+            callback_data: *mut c_void,
+            sources: CFArrayRef,
+            error: CFStringRef,
+        ),
+    );
+    fn LKCreateScreenShareTrackForWindow(window: *const c_void) -> *const c_void;
+    fn LKCreateMicrophoneTrack() -> *const c_void;
+    fn LKLocalTrackSetMute(publication: *const c_void, muted: bool);
 }
 
 pub type Sid = String;
 
+/// A human-readable explanation of why a room disconnected, surfaced by the native layer (e.g.
+/// "network connection lost" or "kicked by host"). `None` means no reason was given: the room
+/// hasn't connected yet, `Room::disconnect` was called, or the native layer reported none.
+pub type DisconnectReason = String;
+
 #[derive(Clone, Eq, PartialEq)]
 pub enum ConnectionState {
-    Disconnected,
+    Disconnected { reason: Option<DisconnectReason> },
+    Connecting,
     Connected { url: String, token: String },
+    Reconnecting,
+}
+
+/// The state of the room's single local audio (microphone) track. Tracked as a state enum,
+/// rather than just an `Option<LocalTrackPublication>`, so that `set_mute` can be called while a
+/// `publish_audio_track` call is still in flight -- the requested mute state is stashed on
+/// `Pending` and applied to the native track as soon as the FFI publish callback resolves it into
+/// `Published`.
+pub enum LocalTrack {
+    None,
+    Pending { publish_id: u32, muted: bool },
+    Published {
+        track_publication: LocalTrackPublication,
+        muted: bool,
+    },
 }
 
 pub struct Room {
@@ -89,7 +143,10 @@ pub struct Room {
         watch::Sender<ConnectionState>,
         watch::Receiver<ConnectionState>,
     )>,
+    local_audio_track: Mutex<LocalTrack>,
+    next_local_track_publish_id: AtomicU32,
     remote_video_track_subscribers: Mutex<Vec<mpsc::UnboundedSender<RemoteVideoTrackUpdate>>>,
+    remote_audio_track_subscribers: Mutex<Vec<mpsc::UnboundedSender<RemoteAudioTrackUpdate>>>,
     _delegate: RoomDelegate,
 }
 
@@ -99,8 +156,13 @@ impl Room {
             let delegate = RoomDelegate::new(weak_room.clone());
             Self {
                 native_room: unsafe { LKRoomCreate(delegate.native_delegate) },
-                connection: Mutex::new(watch::channel_with(ConnectionState::Disconnected)),
+                connection: Mutex::new(watch::channel_with(ConnectionState::Disconnected {
+                    reason: None,
+                })),
+                local_audio_track: Mutex::new(LocalTrack::None),
+                next_local_track_publish_id: AtomicU32::new(0),
                 remote_video_track_subscribers: Default::default(),
+                remote_audio_track_subscribers: Default::default(),
                 _delegate: delegate,
             }
         })
@@ -111,6 +173,35 @@ impl Room {
     }
 
     pub fn connect(self: &Arc<Self>, url: &str, token: &str) -> impl Future<Output = Result<()>> {
+        self.connect_internal(url, token, ConnectionState::Connecting)
+    }
+
+    /// Reconnects using the url/token of the room's current `Connected` state, emitting
+    /// `Reconnecting` (rather than `Connecting`) for the duration of the attempt so a UI watching
+    /// `status()` can tell a recovery attempt apart from a brand new connection. Returns an error
+    /// if the room isn't `Connected` when this is called, e.g. after `disconnect` or a dropped
+    /// connection has already moved it to `Disconnected`.
+    pub fn reconnect(self: &Arc<Self>) -> impl Future<Output = Result<()>> {
+        let previous_connection = self.connection.lock().0.borrow().clone();
+        let this = self.clone();
+        async move {
+            let (url, token) = match previous_connection {
+                ConnectionState::Connected { url, token } => (url, token),
+                _ => return Err(anyhow!("cannot reconnect a room that isn't connected")),
+            };
+            this.connect_internal(&url, &token, ConnectionState::Reconnecting)
+                .await
+        }
+    }
+
+    fn connect_internal(
+        self: &Arc<Self>,
+        url: &str,
+        token: &str,
+        state_while_connecting: ConnectionState,
+    ) -> impl Future<Output = Result<()>> {
+        *self.connection.lock().0.borrow_mut() = state_while_connecting;
+
         let url = CFString::new(url);
         let token = CFString::new(token);
         let (did_connect, tx, rx) = Self::build_done_callback();
@@ -134,13 +225,26 @@ impl Room {
                         ConnectionState::Connected { url, token };
                     Ok(())
                 }
-                Err(err) => Err(err),
+                Err(err) => {
+                    this.did_disconnect(Some(err.to_string()));
+                    Err(err)
+                }
             }
         }
     }
 
-    fn did_disconnect(&self) {
-        *self.connection.lock().0.borrow_mut() = ConnectionState::Disconnected;
+    /// Disconnects from the room, if connected. Safe to call more than once, or on a room that
+    /// was never connected -- `LKRoomDisconnect` is a no-op in that case, matching the behavior
+    /// `Drop` already relied on before this was exposed as its own method.
+    pub fn disconnect(&self) {
+        unsafe {
+            LKRoomDisconnect(self.native_room);
+        }
+        self.did_disconnect(None);
+    }
+
+    fn did_disconnect(&self, reason: Option<DisconnectReason>) {
+        *self.connection.lock().0.borrow_mut() = ConnectionState::Disconnected { reason };
     }
 
     pub fn display_sources(self: &Arc<Self>) -> impl Future<Output = Result<Vec<MacOSDisplay>>> {
@@ -170,6 +274,36 @@ impl Room {
         async move { rx.await.unwrap() }
     }
 
+    /// Like `display_sources`, but enumerates individual on-screen windows rather than whole
+    /// displays, so callers can offer sharing a single application window instead of the entire
+    /// screen.
+    pub fn window_sources(self: &Arc<Self>) -> impl Future<Output = Result<Vec<MacOSWindow>>> {
+        extern "C" fn callback(tx: *mut c_void, sources: CFArrayRef, error: CFStringRef) {
+            unsafe {
+                let tx = Box::from_raw(tx as *mut oneshot::Sender<Result<Vec<MacOSWindow>>>);
+
+                if sources.is_null() {
+                    let _ = tx.send(Err(anyhow!("{}", CFString::wrap_under_get_rule(error))));
+                } else {
+                    let sources = CFArray::wrap_under_get_rule(sources)
+                        .into_iter()
+                        .map(|source| MacOSWindow::new(*source))
+                        .collect();
+
+                    let _ = tx.send(Ok(sources));
+                }
+            }
+        }
+
+        let (tx, rx) = oneshot::channel();
+
+        unsafe {
+            LKWindowSources(Box::into_raw(Box::new(tx)) as *mut _, callback);
+        }
+
+        async move { rx.await.unwrap() }
+    }
+
     pub fn publish_video_track(
         self: &Arc<Self>,
         track: &LocalVideoTrack,
@@ -196,7 +330,110 @@ impl Room {
         async { rx.await.unwrap().context("error publishing video track") }
     }
 
+    pub fn publish_audio_track(
+        self: &Arc<Self>,
+        track: &LocalAudioTrack,
+    ) -> impl Future<Output = Result<LocalTrackPublication>> {
+        let publish_id = self.next_local_track_publish_id.fetch_add(1, SeqCst);
+        *self.local_audio_track.lock() = LocalTrack::Pending {
+            publish_id,
+            muted: false,
+        };
+
+        let (tx, rx) = oneshot::channel::<Result<LocalTrackPublication>>();
+        extern "C" fn callback(tx: *mut c_void, publication: *mut c_void, error: CFStringRef) {
+            let tx =
+                unsafe { Box::from_raw(tx as *mut oneshot::Sender<Result<LocalTrackPublication>>) };
+            if error.is_null() {
+                let _ = tx.send(Ok(LocalTrackPublication(publication)));
+            } else {
+                let error = unsafe { CFString::wrap_under_get_rule(error).to_string() };
+                let _ = tx.send(Err(anyhow!(error)));
+            }
+        }
+        unsafe {
+            LKRoomPublishAudioTrack(
+                self.native_room,
+                track.0,
+                callback,
+                Box::into_raw(Box::new(tx)) as *mut c_void,
+            );
+        }
+
+        let this = self.clone();
+        async move {
+            let publication = rx.await.unwrap().context("error publishing audio track")?;
+
+            let mut local_audio_track = this.local_audio_track.lock();
+            if let LocalTrack::Pending {
+                publish_id: pending_id,
+                muted,
+            } = &*local_audio_track
+            {
+                if *pending_id == publish_id {
+                    let muted = *muted;
+                    if muted {
+                        unsafe { LKLocalTrackSetMute(publication.0, true) };
+                    }
+                    *local_audio_track = LocalTrack::Published {
+                        track_publication: publication.clone(),
+                        muted,
+                    };
+                }
+            }
+            drop(local_audio_track);
+
+            Ok(publication)
+        }
+    }
+
+    /// Mutes or unmutes the local microphone track. If `publish_audio_track` is still in flight,
+    /// the requested state is stashed and applied to the native track once publishing completes.
+    /// Has no effect if there is no local audio track at all.
+    pub fn set_mute(&self, muted: bool) {
+        let mut local_audio_track = self.local_audio_track.lock();
+        match &mut *local_audio_track {
+            LocalTrack::None => {}
+            LocalTrack::Pending {
+                muted: pending_muted,
+                ..
+            } => {
+                *pending_muted = muted;
+            }
+            LocalTrack::Published {
+                track_publication,
+                muted: published_muted,
+            } => {
+                unsafe { LKLocalTrackSetMute(track_publication.0, muted) };
+                *published_muted = muted;
+            }
+        }
+    }
+
+    pub fn is_sharing_mic(&self) -> bool {
+        !matches!(&*self.local_audio_track.lock(), LocalTrack::None)
+    }
+
+    pub fn is_muted(&self) -> bool {
+        match &*self.local_audio_track.lock() {
+            LocalTrack::None => false,
+            LocalTrack::Pending { muted, .. } => *muted,
+            LocalTrack::Published { muted, .. } => *muted,
+        }
+    }
+
     pub fn unpublish_track(&self, publication: LocalTrackPublication) {
+        let mut local_audio_track = self.local_audio_track.lock();
+        if let LocalTrack::Published {
+            track_publication, ..
+        } = &*local_audio_track
+        {
+            if track_publication.0 == publication.0 {
+                *local_audio_track = LocalTrack::None;
+            }
+        }
+        drop(local_audio_track);
+
         unsafe {
             LKRoomUnpublishTrack(self.native_room, publication.0);
         }
@@ -237,6 +474,12 @@ impl Room {
         rx
     }
 
+    pub fn remote_audio_track_updates(&self) -> mpsc::UnboundedReceiver<RemoteAudioTrackUpdate> {
+        let (tx, rx) = mpsc::unbounded();
+        self.remote_audio_track_subscribers.lock().push(tx);
+        rx
+    }
+
     fn did_subscribe_to_remote_video_track(&self, track: RemoteVideoTrack) {
         let track = Arc::new(track);
         self.remote_video_track_subscribers.lock().retain(|tx| {
@@ -255,6 +498,24 @@ impl Room {
         });
     }
 
+    fn did_subscribe_to_remote_audio_track(&self, track: RemoteAudioTrack) {
+        let track = Arc::new(track);
+        self.remote_audio_track_subscribers.lock().retain(|tx| {
+            tx.unbounded_send(RemoteAudioTrackUpdate::Subscribed(track.clone()))
+                .is_ok()
+        });
+    }
+
+    fn did_unsubscribe_from_remote_audio_track(&self, publisher_id: String, track_id: String) {
+        self.remote_audio_track_subscribers.lock().retain(|tx| {
+            tx.unbounded_send(RemoteAudioTrackUpdate::Unsubscribed {
+                publisher_id: publisher_id.clone(),
+                track_id: track_id.clone(),
+            })
+            .is_ok()
+        });
+    }
+
     fn build_done_callback() -> (
         extern "C" fn(*mut c_void, CFStringRef),
         *mut c_void,
@@ -287,6 +548,45 @@ impl Drop for Room {
     }
 }
 
+impl RoomBackend for Room {
+    type LocalVideoTrack = LocalVideoTrack;
+    type LocalTrackPublication = LocalTrackPublication;
+    type RemoteVideoTrack = RemoteVideoTrack;
+    type RemoteVideoTrackUpdate = RemoteVideoTrackUpdate;
+    type MacOSDisplay = MacOSDisplay;
+
+    fn connect(self: &Arc<Self>, url: &str, token: &str) -> BoxFuture<'static, Result<()>> {
+        Box::pin(Room::connect(self, url, token))
+    }
+
+    fn disconnect(&self) {
+        Room::disconnect(self)
+    }
+
+    fn display_sources(self: &Arc<Self>) -> BoxFuture<'static, Result<Vec<MacOSDisplay>>> {
+        Box::pin(Room::display_sources(self))
+    }
+
+    fn publish_video_track(
+        self: &Arc<Self>,
+        track: &LocalVideoTrack,
+    ) -> BoxFuture<'static, Result<LocalTrackPublication>> {
+        Box::pin(Room::publish_video_track(self, track))
+    }
+
+    fn unpublish_track(&self, publication: LocalTrackPublication) {
+        Room::unpublish_track(self, publication)
+    }
+
+    fn remote_video_tracks(&self, participant_id: &str) -> Vec<Arc<RemoteVideoTrack>> {
+        Room::remote_video_tracks(self, participant_id)
+    }
+
+    fn remote_video_track_updates(&self) -> mpsc::UnboundedReceiver<RemoteVideoTrackUpdate> {
+        Room::remote_video_track_updates(self)
+    }
+}
+
 struct RoomDelegate {
     native_delegate: *const c_void,
     weak_room: *const Room,
@@ -301,6 +601,8 @@ impl RoomDelegate {
                 Self::on_did_disconnect,
                 Self::on_did_subscribe_to_remote_video_track,
                 Self::on_did_unsubscribe_from_remote_video_track,
+                Self::on_did_subscribe_to_remote_audio_track,
+                Self::on_did_unsubscribe_from_remote_audio_track,
             )
         };
         Self {
@@ -309,10 +611,15 @@ impl RoomDelegate {
         }
     }
 
-    extern "C" fn on_did_disconnect(room: *mut c_void) {
+    extern "C" fn on_did_disconnect(room: *mut c_void, reason: CFStringRef) {
         let room = unsafe { Weak::from_raw(room as *mut Room) };
+        let reason = if reason.is_null() {
+            None
+        } else {
+            Some(unsafe { CFString::wrap_under_get_rule(reason).to_string() })
+        };
         if let Some(room) = room.upgrade() {
-            room.did_disconnect();
+            room.did_disconnect(reason);
         }
         let _ = Weak::into_raw(room);
     }
@@ -346,6 +653,36 @@ impl RoomDelegate {
         }
         let _ = Weak::into_raw(room);
     }
+
+    extern "C" fn on_did_subscribe_to_remote_audio_track(
+        room: *mut c_void,
+        publisher_id: CFStringRef,
+        track_id: CFStringRef,
+        track: *const c_void,
+    ) {
+        let room = unsafe { Weak::from_raw(room as *mut Room) };
+        let publisher_id = unsafe { CFString::wrap_under_get_rule(publisher_id).to_string() };
+        let track_id = unsafe { CFString::wrap_under_get_rule(track_id).to_string() };
+        let track = RemoteAudioTrack::new(track, track_id, publisher_id);
+        if let Some(room) = room.upgrade() {
+            room.did_subscribe_to_remote_audio_track(track);
+        }
+        let _ = Weak::into_raw(room);
+    }
+
+    extern "C" fn on_did_unsubscribe_from_remote_audio_track(
+        room: *mut c_void,
+        publisher_id: CFStringRef,
+        track_id: CFStringRef,
+    ) {
+        let room = unsafe { Weak::from_raw(room as *mut Room) };
+        let publisher_id = unsafe { CFString::wrap_under_get_rule(publisher_id).to_string() };
+        let track_id = unsafe { CFString::wrap_under_get_rule(track_id).to_string() };
+        if let Some(room) = room.upgrade() {
+            room.did_unsubscribe_from_remote_audio_track(publisher_id, track_id);
+        }
+        let _ = Weak::into_raw(room);
+    }
 }
 
 impl Drop for RoomDelegate {
@@ -363,6 +700,10 @@ impl LocalVideoTrack {
     pub fn screen_share_for_display(display: &MacOSDisplay) -> Self {
         Self(unsafe { LKCreateScreenShareTrackForDisplay(display.0) })
     }
+
+    pub fn screen_share_for_window(window: &MacOSWindow) -> Self {
+        Self(unsafe { LKCreateScreenShareTrackForWindow(window.0) })
+    }
 }
 
 impl Drop for LocalVideoTrack {
@@ -371,8 +712,29 @@ impl Drop for LocalVideoTrack {
     }
 }
 
+pub struct LocalAudioTrack(*const c_void);
+
+impl LocalAudioTrack {
+    pub fn create_microphone_track() -> Self {
+        Self(unsafe { LKCreateMicrophoneTrack() })
+    }
+}
+
+impl Drop for LocalAudioTrack {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) }
+    }
+}
+
 pub struct LocalTrackPublication(*const c_void);
 
+impl Clone for LocalTrackPublication {
+    fn clone(&self) -> Self {
+        unsafe { CFRetain(self.0) };
+        Self(self.0)
+    }
+}
+
 impl Drop for LocalTrackPublication {
     fn drop(&mut self) {
         unsafe { CFRelease(self.0) }
@@ -458,6 +820,45 @@ pub enum RemoteVideoTrackUpdate {
     Unsubscribed { publisher_id: Sid, track_id: Sid },
 }
 
+#[derive(Debug)]
+pub struct RemoteAudioTrack {
+    native_track: *const c_void,
+    sid: Sid,
+    publisher_id: String,
+}
+
+impl RemoteAudioTrack {
+    fn new(native_track: *const c_void, sid: Sid, publisher_id: String) -> Self {
+        unsafe {
+            CFRetain(native_track);
+        }
+        Self {
+            native_track,
+            sid,
+            publisher_id,
+        }
+    }
+
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    pub fn publisher_id(&self) -> &str {
+        &self.publisher_id
+    }
+}
+
+impl Drop for RemoteAudioTrack {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.native_track) }
+    }
+}
+
+pub enum RemoteAudioTrackUpdate {
+    Subscribed(Arc<RemoteAudioTrack>),
+    Unsubscribed { publisher_id: Sid, track_id: Sid },
+}
+
 pub struct MacOSDisplay(*const c_void);
 
 impl MacOSDisplay {
@@ -475,6 +876,26 @@ impl Drop for MacOSDisplay {
     }
 }
 
+/// A single on-screen window, as opposed to a whole `MacOSDisplay`. The native window picker
+/// keeps track of each window's id/title/owner; this handle just needs to stay alive long enough
+/// to hand back to `LKCreateScreenShareTrackForWindow`.
+pub struct MacOSWindow(*const c_void);
+
+impl MacOSWindow {
+    fn new(ptr: *const c_void) -> Self {
+        unsafe {
+            CFRetain(ptr);
+        }
+        Self(ptr)
+    }
+}
+
+impl Drop for MacOSWindow {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) }
+    }
+}
+
 #[derive(Clone)]
 pub struct Frame(CVImageBuffer);
 
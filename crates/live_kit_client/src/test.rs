@@ -0,0 +1,451 @@
+use crate::{
+    prod::{ConnectionState, Sid},
+    RoomBackend,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::{
+    channel::mpsc,
+    future::BoxFuture,
+    Future,
+};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use postage::watch;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        Arc, Weak,
+    },
+};
+
+lazy_static! {
+    /// Every `TestServer` currently registered, keyed by its `url` -- the same process-wide
+    /// registry a real client would reach over the network, except `Room::connect` looks requests
+    /// up in here instead of dialing out.
+    static ref SERVERS: Mutex<HashMap<String, Arc<TestServer>>> = Default::default();
+    static ref NEXT_SID: AtomicU64 = AtomicU64::new(0);
+}
+
+fn new_sid(prefix: &str) -> Sid {
+    format!("{}_{}", prefix, NEXT_SID.fetch_add(1, SeqCst))
+}
+
+/// The claims carried by a test access token. Mirrors the shape of a real LiveKit access token
+/// closely enough for `Room::connect` to exercise the same "does this token actually grant access
+/// to this room" check a production client relies on: `sub` is the participant's identity, and
+/// `video.room` is the room it's allowed to join.
+#[derive(Serialize, Deserialize)]
+struct TestTokenClaims {
+    sub: String,
+    /// Expiry, in seconds since the Unix epoch; `decode_token`'s default `Validation` requires it.
+    exp: u64,
+    video: TestTokenVideoGrant,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TestTokenVideoGrant {
+    room: String,
+}
+
+/// An in-process stand-in for a LiveKit SFU deployment, so tests can exercise `Room`/call logic
+/// without the native LiveKit framework. Register one per test with a unique `url` (e.g. the test
+/// name), mint tokens for participants with `create_room_token`, and connect `Room`s to it exactly
+/// as you would a real server.
+pub struct TestServer {
+    url: String,
+    api_key: String,
+    secret_key: String,
+    rooms: Mutex<HashMap<String, Vec<Weak<Room>>>>,
+}
+
+impl TestServer {
+    pub fn create(url: String, api_key: String, secret_key: String) -> Result<Arc<Self>> {
+        let mut servers = SERVERS.lock();
+        if servers.contains_key(&url) {
+            return Err(anyhow!("a test server is already registered at {}", url));
+        }
+        let server = Arc::new(Self {
+            url: url.clone(),
+            api_key,
+            secret_key,
+            rooms: Default::default(),
+        });
+        servers.insert(url, server.clone());
+        Ok(server)
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    /// Mints an access token granting `identity` entry to `room_name`, the way the real LiveKit
+    /// `livekit-server-sdk` issues one -- signed with this server's secret key, so only a `Room`
+    /// connecting to this same `TestServer` can redeem it.
+    pub fn create_room_token(&self, identity: &str, room_name: &str) -> String {
+        let exp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60 * 60;
+        let claims = TestTokenClaims {
+            sub: identity.to_string(),
+            exp,
+            video: TestTokenVideoGrant {
+                room: room_name.to_string(),
+            },
+        };
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(self.secret_key.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn decode_token(&self, token: &str) -> Result<TestTokenClaims> {
+        Ok(jsonwebtoken::decode::<TestTokenClaims>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(self.secret_key.as_bytes()),
+            &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
+        )
+        .context("invalid access token")?
+        .claims)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        SERVERS.lock().remove(&self.url);
+    }
+}
+
+pub type MacOSDisplay = Sid;
+
+pub struct Room {
+    connection: Mutex<(
+        watch::Sender<ConnectionState>,
+        watch::Receiver<ConnectionState>,
+    )>,
+    /// The server and room name this `Room` last successfully connected to, used to find the
+    /// other participants to fan synthetic track updates to.
+    membership: Mutex<Option<(Arc<TestServer>, String)>>,
+    identity: Mutex<Option<String>>,
+    remote_video_tracks: Mutex<HashMap<Sid, Arc<RemoteVideoTrack>>>,
+    remote_video_track_subscribers: Mutex<Vec<mpsc::UnboundedSender<RemoteVideoTrackUpdate>>>,
+}
+
+impl Room {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            connection: Mutex::new(watch::channel_with(ConnectionState::Disconnected {
+                reason: None,
+            })),
+            membership: Default::default(),
+            identity: Default::default(),
+            remote_video_tracks: Default::default(),
+            remote_video_track_subscribers: Default::default(),
+        })
+    }
+
+    pub fn status(&self) -> watch::Receiver<ConnectionState> {
+        self.connection.lock().1.clone()
+    }
+
+    pub fn connect(self: &Arc<Self>, url: &str, token: &str) -> impl Future<Output = Result<()>> {
+        self.connect_internal(url, token, ConnectionState::Connecting)
+    }
+
+    fn connect_internal(
+        self: &Arc<Self>,
+        url: &str,
+        token: &str,
+        state_while_connecting: ConnectionState,
+    ) -> impl Future<Output = Result<()>> {
+        *self.connection.lock().0.borrow_mut() = state_while_connecting;
+        let this = self.clone();
+        let url = url.to_string();
+        let token = token.to_string();
+        async move {
+            let result: Result<()> = (|| {
+                let server = SERVERS
+                    .lock()
+                    .get(&url)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("no test server is registered at {}", url))?;
+                let claims = server.decode_token(&token)?;
+
+                server
+                    .rooms
+                    .lock()
+                    .entry(claims.video.room.clone())
+                    .or_default()
+                    .push(Arc::downgrade(&this));
+                *this.identity.lock() = Some(claims.sub);
+                *this.membership.lock() = Some((server, claims.video.room));
+                Ok(())
+            })();
+
+            match result {
+                Ok(()) => {
+                    *this.connection.lock().0.borrow_mut() =
+                        ConnectionState::Connected { url, token };
+                    Ok(())
+                }
+                Err(err) => {
+                    *this.connection.lock().0.borrow_mut() = ConnectionState::Disconnected {
+                        reason: Some(err.to_string()),
+                    };
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    /// Reconnects using the url/token of this room's current `Connected` state, and returns an
+    /// error if the room isn't `Connected` (e.g. after `disconnect`). There's no
+    /// simulated network in the test backend to actually drop, so this just re-validates the
+    /// token and re-registers with the server -- its purpose is to let tests exercise the
+    /// `Reconnecting` status transition the same way they'd see it against a real server.
+    pub fn reconnect(self: &Arc<Self>) -> impl Future<Output = Result<()>> {
+        let previous_connection = self.connection.lock().0.borrow().clone();
+        let this = self.clone();
+        async move {
+            let (url, token) = match previous_connection {
+                ConnectionState::Connected { url, token } => (url, token),
+                _ => return Err(anyhow!("cannot reconnect a room that isn't connected")),
+            };
+            this.connect_internal(&url, &token, ConnectionState::Reconnecting)
+                .await
+        }
+    }
+
+    pub fn disconnect(&self) {
+        self.membership.lock().take();
+        *self.connection.lock().0.borrow_mut() = ConnectionState::Disconnected { reason: None };
+    }
+
+    pub fn display_sources(self: &Arc<Self>) -> impl Future<Output = Result<Vec<MacOSDisplay>>> {
+        async { Ok(vec![new_sid("DI")]) }
+    }
+
+    pub fn publish_video_track(
+        self: &Arc<Self>,
+        _track: &LocalVideoTrack,
+    ) -> impl Future<Output = Result<LocalTrackPublication>> {
+        let this = self.clone();
+        async move {
+            let (server, room_name) = this
+                .membership
+                .lock()
+                .clone()
+                .ok_or_else(|| anyhow!("cannot publish a track before connecting to a room"))?;
+            let publisher_id = this
+                .identity
+                .lock()
+                .clone()
+                .ok_or_else(|| anyhow!("cannot publish a track before connecting to a room"))?;
+            let sid = new_sid("TR");
+
+            let participants = server
+                .rooms
+                .lock()
+                .get(&room_name)
+                .cloned()
+                .unwrap_or_default();
+            for participant in participants {
+                let Some(participant) = participant.upgrade() else {
+                    continue;
+                };
+                if Arc::ptr_eq(&participant, &this) {
+                    continue;
+                }
+                let track = Arc::new(RemoteVideoTrack {
+                    sid: sid.clone(),
+                    publisher_id: publisher_id.clone(),
+                });
+                participant
+                    .remote_video_tracks
+                    .lock()
+                    .insert(sid.clone(), track.clone());
+                participant
+                    .remote_video_track_subscribers
+                    .lock()
+                    .retain(|tx| {
+                        tx.unbounded_send(RemoteVideoTrackUpdate::Subscribed(track.clone()))
+                            .is_ok()
+                    });
+            }
+
+            Ok(LocalTrackPublication { sid })
+        }
+    }
+
+    pub fn unpublish_track(&self, publication: LocalTrackPublication) {
+        let Some((server, room_name)) = self.membership.lock().clone() else {
+            return;
+        };
+        let publisher_id = self.identity.lock().clone().unwrap_or_default();
+        let participants = server
+            .rooms
+            .lock()
+            .get(&room_name)
+            .cloned()
+            .unwrap_or_default();
+        for participant in participants {
+            let Some(participant) = participant.upgrade() else {
+                continue;
+            };
+            if participant
+                .remote_video_tracks
+                .lock()
+                .remove(&publication.sid)
+                .is_none()
+            {
+                continue;
+            }
+            participant
+                .remote_video_track_subscribers
+                .lock()
+                .retain(|tx| {
+                    tx.unbounded_send(RemoteVideoTrackUpdate::Unsubscribed {
+                        publisher_id: publisher_id.clone(),
+                        track_id: publication.sid.clone(),
+                    })
+                    .is_ok()
+                });
+        }
+    }
+
+    pub fn remote_video_tracks(&self, participant_id: &str) -> Vec<Arc<RemoteVideoTrack>> {
+        self.remote_video_tracks
+            .lock()
+            .values()
+            .filter(|track| track.publisher_id == participant_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn remote_video_track_updates(&self) -> mpsc::UnboundedReceiver<RemoteVideoTrackUpdate> {
+        let (tx, rx) = mpsc::unbounded();
+        self.remote_video_track_subscribers.lock().push(tx);
+        rx
+    }
+}
+
+impl RoomBackend for Room {
+    type LocalVideoTrack = LocalVideoTrack;
+    type LocalTrackPublication = LocalTrackPublication;
+    type RemoteVideoTrack = RemoteVideoTrack;
+    type RemoteVideoTrackUpdate = RemoteVideoTrackUpdate;
+    type MacOSDisplay = MacOSDisplay;
+
+    fn connect(self: &Arc<Self>, url: &str, token: &str) -> BoxFuture<'static, Result<()>> {
+        Box::pin(Room::connect(self, url, token))
+    }
+
+    fn disconnect(&self) {
+        Room::disconnect(self)
+    }
+
+    fn display_sources(self: &Arc<Self>) -> BoxFuture<'static, Result<Vec<MacOSDisplay>>> {
+        Box::pin(Room::display_sources(self))
+    }
+
+    fn publish_video_track(
+        self: &Arc<Self>,
+        track: &LocalVideoTrack,
+    ) -> BoxFuture<'static, Result<LocalTrackPublication>> {
+        Box::pin(Room::publish_video_track(self, track))
+    }
+
+    fn unpublish_track(&self, publication: LocalTrackPublication) {
+        Room::unpublish_track(self, publication)
+    }
+
+    fn remote_video_tracks(&self, participant_id: &str) -> Vec<Arc<RemoteVideoTrack>> {
+        Room::remote_video_tracks(self, participant_id)
+    }
+
+    fn remote_video_track_updates(&self) -> mpsc::UnboundedReceiver<RemoteVideoTrackUpdate> {
+        Room::remote_video_track_updates(self)
+    }
+}
+
+pub struct LocalVideoTrack;
+
+impl LocalVideoTrack {
+    pub fn screen_share_for_display(_display: &MacOSDisplay) -> Self {
+        Self
+    }
+}
+
+pub struct LocalTrackPublication {
+    sid: Sid,
+}
+
+#[derive(Debug)]
+pub struct RemoteVideoTrack {
+    sid: Sid,
+    publisher_id: String,
+}
+
+impl RemoteVideoTrack {
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    pub fn publisher_id(&self) -> &str {
+        &self.publisher_id
+    }
+
+    /// Real `RemoteVideoTrack`s stream decoded frames in from the native renderer; there's no
+    /// native decoder in test mode, so this is always empty. Tests that need to assert on frame
+    /// contents should construct a `Frame` directly with `Frame::new` instead of going through a
+    /// track at all.
+    pub fn frames(&self) -> async_broadcast::Receiver<Frame> {
+        let (_tx, rx) = async_broadcast::broadcast(1);
+        rx
+    }
+}
+
+pub enum RemoteVideoTrackUpdate {
+    Subscribed(Arc<RemoteVideoTrack>),
+    Unsubscribed { publisher_id: Sid, track_id: Sid },
+}
+
+/// A plain in-memory pixel buffer standing in for the real `CVImageBuffer`-backed `Frame`, so
+/// tests can assert on `width()`/`height()` without a real decoded video frame.
+#[derive(Clone)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    buffer: Arc<Vec<u8>>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            buffer: Arc::new(vec![0; width * height * 4]),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+}
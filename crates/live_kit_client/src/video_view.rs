@@ -0,0 +1,130 @@
+use crate::{prod::RemoteVideoTrack, Frame, Sid};
+use futures::StreamExt;
+use gpui::{
+    geometry::{
+        rect::RectF,
+        vector::{vec2f, Vector2F},
+    },
+    json::{json, ToJson},
+    presenter::MeasurementContext,
+    scene, DebugContext, Element, LayoutContext, PaintContext, SizeConstraint,
+};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+lazy_static! {
+    /// The most recently received frame for each track we've ever viewed, keyed by sid and shared
+    /// across every `VideoView` for that track -- mirrors how `Image` caches decoded `Uri` sources,
+    /// so the frame subscription is only started once no matter how many times the view re-renders.
+    static ref LATEST_FRAMES: Mutex<HashMap<Sid, Arc<Mutex<Option<Frame>>>>> = Default::default();
+}
+
+/// Paints the most recent frame of a `RemoteVideoTrack`, preserving its aspect ratio within the
+/// space it's given and letterboxing the rest.
+pub struct VideoView {
+    latest_frame: Arc<Mutex<Option<Frame>>>,
+}
+
+impl VideoView {
+    /// Subscribes to `track`'s frames the first time it's viewed, calling `on_frame` (typically
+    /// `cx.notify()`) each time a new one arrives so the owning view can repaint.
+    pub fn new(track: &Arc<RemoteVideoTrack>, on_frame: impl Fn() + Send + Sync + 'static) -> Self {
+        let latest_frame = LATEST_FRAMES
+            .lock()
+            .entry(track.sid().to_string())
+            .or_insert_with(|| {
+                let latest_frame = Arc::new(Mutex::new(None));
+                let mut frames = track.frames();
+                let latest_frame_for_task = latest_frame.clone();
+                smol::spawn(async move {
+                    while let Some(mut frame) = frames.next().await {
+                        // `frames()` is an `async_broadcast` receiver with a bounded buffer; if we
+                        // fell behind, drain it down to the newest frame rather than painting
+                        // every stale one we missed.
+                        while let Ok(newer_frame) = frames.try_recv() {
+                            frame = newer_frame;
+                        }
+                        *latest_frame_for_task.lock() = Some(frame);
+                        on_frame();
+                    }
+                })
+                .detach();
+                latest_frame
+            })
+            .clone();
+
+        Self { latest_frame }
+    }
+}
+
+impl Element for VideoView {
+    type LayoutState = ();
+    type PaintState = ();
+
+    fn layout(
+        &mut self,
+        constraint: SizeConstraint,
+        _: &mut LayoutContext,
+    ) -> (Vector2F, Self::LayoutState) {
+        let size = match self.latest_frame.lock().clone() {
+            Some(frame) => constrain_size_preserving_aspect_ratio(
+                constraint.max,
+                vec2f(frame.width() as f32, frame.height() as f32),
+            ),
+            None => constraint.min,
+        };
+        (size, ())
+    }
+
+    fn paint(
+        &mut self,
+        bounds: RectF,
+        _: RectF,
+        _: &mut Self::LayoutState,
+        cx: &mut PaintContext,
+    ) -> Self::PaintState {
+        if let Some(frame) = self.latest_frame.lock().clone() {
+            let frame_size = vec2f(frame.width() as f32, frame.height() as f32);
+            let size = constrain_size_preserving_aspect_ratio(bounds.size(), frame_size);
+            let origin = bounds.origin() + (bounds.size() - size) / 2.;
+            cx.scene.push_surface(scene::Surface {
+                bounds: RectF::new(origin, size),
+                image_buffer: frame.image(),
+            });
+        }
+    }
+
+    fn rect_for_text_range(
+        &self,
+        _: Range<usize>,
+        _: RectF,
+        _: RectF,
+        _: &Self::LayoutState,
+        _: &Self::PaintState,
+        _: &MeasurementContext,
+    ) -> Option<RectF> {
+        None
+    }
+
+    fn debug(
+        &self,
+        bounds: RectF,
+        _: &Self::LayoutState,
+        _: &Self::PaintState,
+        _: &DebugContext,
+    ) -> serde_json::Value {
+        json!({
+            "type": "VideoView",
+            "bounds": bounds.to_json(),
+        })
+    }
+}
+
+fn constrain_size_preserving_aspect_ratio(max_size: Vector2F, natural_size: Vector2F) -> Vector2F {
+    if max_size.x() / max_size.y() > natural_size.x() / natural_size.y() {
+        vec2f(natural_size.x() * max_size.y() / natural_size.y(), max_size.y())
+    } else {
+        vec2f(max_size.x(), natural_size.y() * max_size.x() / natural_size.x())
+    }
+}
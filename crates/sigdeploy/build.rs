@@ -1,28 +1,38 @@
+use serde::Deserialize;
 use std::process::Command;
 
 fn main() {
-    println!("cargo:rustc-env=MACOSX_DEPLOYMENT_TARGET=10.15.7");
-
-    if let Ok(api_key) = std::env::var("SIGDEPLOY_MIXPANEL_TOKEN") {
-        println!("cargo:rustc-env=SIGDEPLOY_MIXPANEL_TOKEN={api_key}");
-    }
-    if let Ok(api_key) = std::env::var("SIGDEPLOY_AMPLITUDE_API_KEY") {
-        println!("cargo:rustc-env=SIGDEPLOY_AMPLITUDE_API_KEY={api_key}");
+    if is_wasm_target() {
+        // No native linking, no Apple framework wiring, no npm toolchain: just forward the
+        // analytics tokens as compile-time constants and stop.
+        emit_telemetry_env();
+        return;
     }
 
-    if std::env::var("SIGDEPLOY_BUNDLE").ok().as_deref() == Some("true") {
-        // Find WebRTC.framework in the Frameworks folder when running as part of an application bundle.
-        println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path/../Frameworks");
-    } else {
-        // Find WebRTC.framework as a sibling of the executable when running outside of an application bundle.
-        println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path");
+    if cfg!(target_os = "macos") {
+        println!("cargo:rustc-env=MACOSX_DEPLOYMENT_TARGET=10.15.7");
+
+        link_webrtc_framework();
+
+        // Register exported Objective-C selectors, protocols, etc
+        println!("cargo:rustc-link-arg=-Wl,-ObjC");
+
+        link_swift_runtime();
+    } else if cfg!(target_os = "linux") {
+        link_swift_runtime();
     }
 
-    // Seems to be required to enable Swift concurrency
-    println!("cargo:rustc-link-arg=-Wl,-rpath,/usr/lib/swift");
+    emit_telemetry_env();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=../../styles/src");
+    println!("cargo:rerun-if-changed=../../styles/package.json");
+    println!("cargo:rerun-if-changed=../../styles/package-lock.json");
 
-    // Register exported Objective-C selectors, protocols, etc
-    println!("cargo:rustc-link-arg=-Wl,-ObjC");
+    if skip_asset_build() {
+        println!("cargo:warning=skipping npm theme build (cargo_check/analyzer run detected)");
+        return;
+    }
 
     let output = Command::new("npm")
         .current_dir("../../styles")
@@ -47,6 +57,151 @@ fn main() {
             String::from_utf8_lossy(&output.stderr)
         );
     }
+}
 
-    println!("cargo:rerun-if-changed=../../styles/src");
-}
\ No newline at end of file
+/// Compiles in the Mixpanel/Amplitude tokens and (optional) self-hosted/proxy API hosts as
+/// `rustc-env` constants. With the `disable-telemetry` feature, the tokens are never emitted
+/// so a privacy-preserving build can't accidentally ship them; a `SIGDEPLOY_TELEMETRY_DISABLED=1`
+/// sentinel is emitted instead for the runtime to detect.
+fn emit_telemetry_env() {
+    println!("cargo:rerun-if-env-changed=SIGDEPLOY_MIXPANEL_TOKEN");
+    println!("cargo:rerun-if-env-changed=SIGDEPLOY_AMPLITUDE_API_KEY");
+    println!("cargo:rerun-if-env-changed=SIGDEPLOY_MIXPANEL_API_HOST");
+    println!("cargo:rerun-if-env-changed=SIGDEPLOY_AMPLITUDE_API_HOST");
+
+    if cfg!(feature = "disable-telemetry") {
+        println!("cargo:rustc-env=SIGDEPLOY_TELEMETRY_DISABLED=1");
+        return;
+    }
+
+    if let Ok(api_key) = std::env::var("SIGDEPLOY_MIXPANEL_TOKEN") {
+        println!("cargo:rustc-env=SIGDEPLOY_MIXPANEL_TOKEN={api_key}");
+    }
+    if let Ok(api_key) = std::env::var("SIGDEPLOY_AMPLITUDE_API_KEY") {
+        println!("cargo:rustc-env=SIGDEPLOY_AMPLITUDE_API_KEY={api_key}");
+    }
+    if let Ok(host) = std::env::var("SIGDEPLOY_MIXPANEL_API_HOST") {
+        println!("cargo:rustc-env=SIGDEPLOY_MIXPANEL_API_HOST={host}");
+    }
+    if let Ok(host) = std::env::var("SIGDEPLOY_AMPLITUDE_API_HOST") {
+        println!("cargo:rustc-env=SIGDEPLOY_AMPLITUDE_API_HOST={host}");
+    }
+}
+
+/// Wires up WebRTC.framework, in either of two modes selected by cargo feature:
+///
+/// - `dynamic-system` (default): the framework is loaded at runtime from a path next to the
+///   executable, or from `../Frameworks` when running inside an application bundle (as
+///   signaled by `SIGDEPLOY_BUNDLE`).
+/// - `static-bundled`: the framework is linked in directly from `WEBRTC_FRAMEWORK_DIR`, for
+///   reproducible/static distribution builds that don't depend on a loose framework bundle
+///   being present at runtime.
+fn link_webrtc_framework() {
+    println!("cargo:rerun-if-env-changed=SIGDEPLOY_BUNDLE");
+    println!("cargo:rerun-if-env-changed=WEBRTC_FRAMEWORK_DIR");
+
+    if cfg!(feature = "static-bundled") {
+        let framework_dir = std::env::var("WEBRTC_FRAMEWORK_DIR")
+            .expect("WEBRTC_FRAMEWORK_DIR must be set when building with the static-bundled feature");
+        println!("cargo:rustc-link-search=framework={framework_dir}");
+        println!("cargo:rustc-link-lib=framework=WebRTC");
+        return;
+    }
+
+    if std::env::var("SIGDEPLOY_BUNDLE").ok().as_deref() == Some("true") {
+        // Find WebRTC.framework in the Frameworks folder when running as part of an application bundle.
+        println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path/../Frameworks");
+    } else {
+        // Find WebRTC.framework as a sibling of the executable when running outside of an application bundle.
+        println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path");
+    }
+}
+
+/// Whether this invocation is targeting any `wasm32` target (e.g. `wasm32-unknown-unknown`),
+/// which has no native linker and can't shell out to Apple/Swift tooling or the npm theme build.
+fn is_wasm_target() -> bool {
+    std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32")
+        || std::env::var("TARGET")
+            .map(|target| target.contains("wasm32"))
+            .unwrap_or(false)
+}
+
+/// Whether the npm theme build should be skipped: either the `cargo_check` feature is
+/// enabled, or `RUSTC_WRAPPER` or `RUST_ANALYZER_CHECK` is set in the build script's
+/// environment, which is taken as a sign of a `cargo check`/rust-analyzer background check
+/// rather than a real build that needs the bundled assets. Any `RUSTC_WRAPPER` counts, and a
+/// plain `cargo check` with neither variable set still runs the npm build.
+fn skip_asset_build() -> bool {
+    if cfg!(feature = "cargo_check") {
+        return true;
+    }
+
+    std::env::var_os("RUSTC_WRAPPER").is_some() || std::env::var_os("RUST_ANALYZER_CHECK").is_some()
+}
+
+#[derive(Deserialize)]
+struct SwiftTarget {
+    target: SwiftTargetInfo,
+    paths: SwiftPaths,
+}
+
+#[derive(Deserialize)]
+struct SwiftTargetInfo {
+    #[allow(dead_code)]
+    unversioned_triple: String,
+    libraries_require_rpath: bool,
+}
+
+#[derive(Deserialize)]
+struct SwiftPaths {
+    runtime_library_paths: Vec<String>,
+}
+
+/// Discovers the Swift runtime library paths by asking `swiftc`/`swift` for its target info,
+/// the same way swift-rs and splash-rs locate the runtime, and emits the link args needed to
+/// find `libswiftCore`/`Foundation` at runtime.
+fn link_swift_runtime() {
+    let arch = match std::env::consts::ARCH {
+        "aarch64" => "arm64",
+        other => other,
+    };
+
+    let target_triple = if cfg!(target_os = "macos") {
+        format!("{arch}-apple-macosx{}", "10.15.7")
+    } else {
+        format!("{arch}-unknown-linux-gnu")
+    };
+
+    let output = Command::new("swift")
+        .args(["-target", &target_triple, "-print-target-info"])
+        .output()
+        .expect("failed to run swift -print-target-info; is the Swift toolchain installed?");
+    if !output.status.success() {
+        panic!(
+            "swift -print-target-info failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let swift_target: SwiftTarget = serde_json::from_slice(&output.stdout)
+        .expect("failed to parse `swift -print-target-info` output");
+
+    if cfg!(target_os = "macos") && swift_target.target.libraries_require_rpath {
+        panic!(
+            "Swift libraries require rpath on this toolchain; raise MACOSX_DEPLOYMENT_TARGET \
+             in build.rs to a version that doesn't require it"
+        );
+    }
+
+    for path in &swift_target.paths.runtime_library_paths {
+        println!("cargo:rustc-link-search=native={path}");
+        if cfg!(target_os = "linux") {
+            println!("cargo:rustc-link-arg=-Wl,-rpath={path}");
+        }
+    }
+
+    if cfg!(target_os = "linux") {
+        println!("cargo:rustc-link-lib=dylib=swiftCore");
+        println!("cargo:rustc-link-lib=dylib=Foundation");
+    }
+}
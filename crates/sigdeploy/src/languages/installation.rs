@@ -2,14 +2,79 @@ use anyhow::{anyhow, Context, Result};
 use client::http::HttpClient;
 
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use smol::io::AsyncReadExt;
-use std::{path::Path, sync::Arc};
+use std::{
+    io::Write as _,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tempfile::NamedTempFile;
 
 pub struct GitHubLspBinaryVersion {
     pub name: String,
     pub url: String,
 }
 
+/// How many attempts `npm_install_packages` makes in all (the first try plus up to two retries),
+/// and how long it waits before the first retry (doubling after each one), before giving up and
+/// surfacing the last failure's `stderr`.
+/// npm registries see enough transient 5xxs that a bare first-try failure would otherwise abort
+/// language-server installation unnecessarily.
+const NPM_INSTALL_MAX_ATTEMPTS: u32 = 3;
+const NPM_INSTALL_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Points `npm_package_latest_version`/`npm_install_packages` at a specific registry (e.g. a
+/// corporate mirror or an air-gapped Verdaccio instance), optionally authenticating against it.
+#[derive(Clone, Default)]
+pub struct NpmConfig {
+    pub registry: Option<String>,
+    pub auth_token: Option<String>,
+    pub extra_args: Vec<String>,
+}
+
+impl NpmConfig {
+    /// Writes a temporary `.npmrc` reflecting this config (if it specifies anything), returning
+    /// its path via `--userconfig` for the `npm` invocation to pick up. Returns `None` if there's
+    /// nothing to configure, so callers fall back to npm's own default config resolution.
+    fn npmrc(&self) -> Result<Option<NamedTempFile>> {
+        if self.registry.is_none() && self.auth_token.is_none() {
+            return Ok(None);
+        }
+
+        let mut contents = String::new();
+        if let Some(registry) = &self.registry {
+            contents.push_str(&format!("registry={registry}\n"));
+            if let Some(auth_token) = &self.auth_token {
+                let host = registry
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://")
+                    .split('/')
+                    .next()
+                    .unwrap_or(registry);
+                contents.push_str(&format!("//{host}/:_authToken={auth_token}\n"));
+            }
+        }
+
+        let mut file = NamedTempFile::new().context("failed to create temporary .npmrc")?;
+        file.write_all(contents.as_bytes())
+            .context("failed to write temporary .npmrc")?;
+        file.flush().context("failed to flush temporary .npmrc")?;
+        Ok(Some(file))
+    }
+
+    fn apply_to(&self, command: &mut smol::process::Command, npmrc: &Option<NamedTempFile>) {
+        if let Some(registry) = &self.registry {
+            command.arg("--registry").arg(registry);
+        }
+        if let Some(npmrc) = npmrc {
+            command.arg("--userconfig").arg(npmrc.path());
+        }
+        command.args(&self.extra_args);
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct NpmInfo {
@@ -33,14 +98,70 @@ pub(crate) struct GithubRelease {
 pub(crate) struct GithubReleaseAsset {
     pub name: String,
     pub browser_download_url: String,
+    /// Not populated by the GitHub releases API itself, but some release automation attaches it
+    /// directly as `sha256:<hex>` instead of (or alongside) a sibling `.sha256` asset.
+    #[serde(default)]
+    pub digest: Option<String>,
 }
 
-pub async fn npm_package_latest_version(name: &str) -> Result<String> {
-    let output = smol::process::Command::new("npm")
-        .args(["info", name, "--json"])
-        .output()
-        .await
-        .context("failed to run npm info")?;
+/// The OS/architecture this process is running on, used to pick the right asset out of a GitHub
+/// release's `assets` list.
+pub struct TargetTriple {
+    os: &'static str,
+    arch: &'static str,
+}
+
+impl TargetTriple {
+    pub fn current() -> Self {
+        Self {
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+        }
+    }
+
+    fn os_aliases(&self) -> &'static [&'static str] {
+        match self.os {
+            "macos" => &["darwin", "macos", "osx"],
+            "linux" => &["linux"],
+            // Deliberately no bare "win": it's a substring of "darwin", so it could match a macOS
+            // asset name on a Windows host. "win32"/"win64"/"windows" already cover every asset
+            // naming convention we've seen without that false-positive risk.
+            "windows" => &["windows", "win32", "win64"],
+            _ => &[],
+        }
+    }
+
+    fn arch_aliases(&self) -> &'static [&'static str] {
+        match self.arch {
+            "x86_64" => &["x86_64", "amd64", "x64"],
+            "aarch64" => &["aarch64", "arm64"],
+            _ => &[],
+        }
+    }
+}
+
+impl GithubReleaseAsset {
+    /// Picks the asset whose name matches both `target`'s OS and architecture (by substring,
+    /// case-insensitively, against common aliases like `amd64`/`arm64`), or `None` if no asset in
+    /// `assets` matches both.
+    pub fn for_current_platform(
+        assets: &[GithubReleaseAsset],
+        target: TargetTriple,
+    ) -> Option<&GithubReleaseAsset> {
+        assets.iter().find(|asset| {
+            let name = asset.name.to_lowercase();
+            target.os_aliases().iter().any(|os| name.contains(os))
+                && target.arch_aliases().iter().any(|arch| name.contains(arch))
+        })
+    }
+}
+
+pub async fn npm_package_latest_version(name: &str, config: &NpmConfig) -> Result<String> {
+    let npmrc = config.npmrc()?;
+    let mut command = smol::process::Command::new("npm");
+    command.args(["info", name, "--json"]);
+    config.apply_to(&mut command, &npmrc);
+    let output = command.output().await.context("failed to run npm info")?;
     if !output.status.success() {
         Err(anyhow!(
             "failed to execute npm info:\nstdout: {:?}\nstderr: {:?}",
@@ -58,27 +179,39 @@ pub async fn npm_package_latest_version(name: &str) -> Result<String> {
 pub async fn npm_install_packages(
     packages: impl IntoIterator<Item = (&str, &str)>,
     directory: &Path,
+    config: &NpmConfig,
 ) -> Result<()> {
-    let output = smol::process::Command::new("npm")
-        .arg("install")
-        .arg("--prefix")
-        .arg(directory)
-        .args(
-            packages
-                .into_iter()
-                .map(|(name, version)| format!("{name}@{version}")),
-        )
-        .output()
-        .await
-        .context("failed to run npm install")?;
-    if !output.status.success() {
-        Err(anyhow!(
-            "failed to execute npm install:\nstdout: {:?}\nstderr: {:?}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        ))?;
+    let npmrc = config.npmrc()?;
+    let package_args = packages
+        .into_iter()
+        .map(|(name, version)| format!("{name}@{version}"))
+        .collect::<Vec<_>>();
+
+    let mut backoff = NPM_INSTALL_INITIAL_BACKOFF;
+    let mut last_stderr = String::new();
+    for attempt in 0..NPM_INSTALL_MAX_ATTEMPTS {
+        let mut command = smol::process::Command::new("npm");
+        command.arg("install").arg("--prefix").arg(directory);
+        command.args(&package_args);
+        config.apply_to(&mut command, &npmrc);
+
+        let output = command.output().await.context("failed to run npm install")?;
+        if output.status.success() {
+            return Ok(());
+        }
+
+        last_stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if attempt + 1 < NPM_INSTALL_MAX_ATTEMPTS {
+            smol::Timer::after(backoff).await;
+            backoff *= 2;
+        }
     }
-    Ok(())
+
+    Err(anyhow!(
+        "failed to execute npm install after {} attempts:\nstderr: {}",
+        NPM_INSTALL_MAX_ATTEMPTS,
+        last_stderr
+    ))
 }
 
 pub(crate) async fn latest_github_release(
@@ -103,3 +236,97 @@ pub(crate) async fn latest_github_release(
         serde_json::from_slice(body.as_slice()).context("error deserializing latest release")?;
     Ok(release)
 }
+
+/// Downloads `asset`'s binary, verifying it against a published checksum (a `digest` field on
+/// the asset itself, or a sibling `<asset>.sha256` file in `release_assets`) if one is available.
+/// The checksum is computed incrementally as the response body is read, so a mismatch is caught
+/// before any of the bytes are handed back to the caller (and, in turn, before they're ever
+/// written to disk as an executable).
+pub(crate) async fn download_github_release_asset(
+    asset: &GithubReleaseAsset,
+    release_assets: &[GithubReleaseAsset],
+    http: Arc<dyn HttpClient>,
+) -> Result<Vec<u8>> {
+    let expected_checksum = expected_sha256(asset, release_assets, &http).await?;
+
+    let mut response = http
+        .get(&asset.browser_download_url, Default::default(), true)
+        .await
+        .context("error fetching release asset")?;
+
+    let mut hasher = Sha256::new();
+    let mut body = Vec::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let bytes_read = response
+            .body_mut()
+            .read(&mut buffer)
+            .await
+            .context("error reading release asset")?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        body.extend_from_slice(&buffer[..bytes_read]);
+    }
+
+    if let Some(expected) = expected_checksum {
+        let actual = encode_hex(&hasher.finalize());
+        if !actual.eq_ignore_ascii_case(&expected) {
+            return Err(anyhow!(
+                "checksum mismatch downloading {}: expected sha256:{expected}, got sha256:{actual}",
+                asset.name
+            ));
+        }
+    }
+
+    Ok(body)
+}
+
+/// Resolves the checksum `asset` should have, via its own `digest` field or a sibling
+/// `<asset-name>.sha256` file, or `None` if neither is present (most releases don't publish
+/// checksums, so this isn't an error).
+async fn expected_sha256(
+    asset: &GithubReleaseAsset,
+    release_assets: &[GithubReleaseAsset],
+    http: &Arc<dyn HttpClient>,
+) -> Result<Option<String>> {
+    if let Some(digest) = &asset.digest {
+        return Ok(Some(
+            digest.trim_start_matches("sha256:").to_lowercase(),
+        ));
+    }
+
+    let checksum_name = format!("{}.sha256", asset.name);
+    let Some(checksum_asset) = release_assets
+        .iter()
+        .find(|candidate| candidate.name == checksum_name)
+    else {
+        return Ok(None);
+    };
+
+    let mut response = http
+        .get(&checksum_asset.browser_download_url, Default::default(), true)
+        .await
+        .context("error fetching checksum asset")?;
+    let mut body = Vec::new();
+    response
+        .body_mut()
+        .read_to_end(&mut body)
+        .await
+        .context("error reading checksum asset")?;
+
+    Ok(String::from_utf8_lossy(&body)
+        .split_whitespace()
+        .next()
+        .map(|hash| hash.to_lowercase()))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(&mut hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}